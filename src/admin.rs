@@ -1,103 +1,366 @@
-use std::{collections::BTreeMap, future::Future, ops::DerefMut};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{BuildHasherDefault, DefaultHasher},
+};
 
 use anyhow::Result;
+use callback_data::CallbackPayload;
+use chrono::{DateTime, Utc};
 use crossbeam_queue::SegQueue;
-use handler::Handler;
-use serde::Deserialize;
+use rand::Rng;
+use redis::AsyncCommands;
+use reqwest::Url;
+use scheduler::Job;
+use serde::{Deserialize, Serialize};
 use teloxide::{
-    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters},
+    payloads::{
+        AnswerCallbackQuerySetters, EditMessageCaptionSetters, EditMessageMediaSetters, EditMessageTextSetters,
+        SendPhotoSetters,
+    },
     prelude::*,
-    types::{ChatMember, InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode, User},
-};
-use tokio::{
-    sync::{MappedMutexGuard, Mutex, MutexGuard},
-    task::AbortHandle,
-    time::sleep,
+    types::{
+        ChatMember, InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, InputFile, InputMedia,
+        InputMediaPhoto, MessageId, ParseMode, ReplyParameters, User,
+    },
 };
+use tokio::sync::Mutex;
 
-use crate::{Bot, question, utils::*};
+use crate::{
+    Bot,
+    question::{self, QuestionKind},
+    utils::*,
+};
 
 pub mod auth_database;
+pub mod auth_transfer;
+pub mod callback_data;
+pub mod channel_handler;
 pub mod handler;
 pub mod join_handler;
+pub mod join_request_handler;
+pub mod link_detector;
 pub mod link_handler;
+pub mod link_lists;
+pub mod lockdown;
+pub mod penalty;
+pub mod policy;
+pub mod reputation;
+pub mod scheduler;
+pub mod test_handler;
 
-pub const AUTHED_USERS_KEY: &str = "shit_bot_authed_users";
+const WATING_QUESTIONS_KEY: &str = "shit_bot_waiting_questions";
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Chat and message id of a question message, message ids alone collide between chats.
+pub type QuestionKey = (ChatId, MessageId);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct QuestionData {
     pub user: User,
     pub chat_id: ChatId,
+    #[serde(default)]
+    pub private: bool, // question is sent to the private chat with the user instead of `chat_id`
+    #[serde(default)]
+    pub token: Option<String>, // deep link token, set while waiting for the user to open the private chat
+    #[serde(default)]
+    pub prompt: Option<MessageId>, // group message that pointed the user to the private chat
     pub message_id: Option<MessageId>, // spam message
     pub correct: usize,
-    pub title: &'static str,
-    pub options: Vec<&'static String>,
+    pub title: String,
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub kind: QuestionKind,
+    #[serde(default)]
+    pub answers: Vec<String>, // accepted answers of a text question
     pub tried_times: u8,
-    pub cas: Option<MessageId>, // i32 is message id
-    pub left_minutes: u8,
-    pub handler: handler::HandlerKind,
+    pub cas: Option<MessageId>, // reputation warning message
+    #[serde(default)]
+    pub nonce: u32, // renewed with the question, buttons of older versions are rejected
+    pub deadline: DateTime<Utc>,
+    pub handler: String, // name of the registered handler
+    #[serde(default)]
+    pub hidden: Option<link_handler::HiddenMessage>, // spam message deleted while the question is pending
 }
 
 impl QuestionData {
+    /// A question without content yet, fill it with [`QuestionData::renew_question`].
+    pub fn new(user: User, chat_id: ChatId, handler: &str, kind: QuestionKind, deadline: DateTime<Utc>) -> Self {
+        Self {
+            user,
+            chat_id,
+            private: false,
+            token: None,
+            prompt: None,
+            message_id: None,
+            correct: 0,
+            title: String::new(),
+            options: vec![],
+            kind,
+            answers: vec![],
+            tried_times: 0,
+            cas: None,
+            nonce: rand::rng().random(),
+            deadline,
+            handler: handler.to_string(),
+            hidden: None,
+        }
+    }
+
+    pub fn question_chat(&self) -> ChatId {
+        if self.private {
+            self.user.id.into()
+        } else {
+            self.chat_id
+        }
+    }
+
+    pub fn remaining(&self) -> chrono::Duration {
+        (self.deadline - Utc::now()).max(chrono::Duration::zero())
+    }
+
+    pub fn left_minutes(&self) -> i64 {
+        (self.remaining().num_seconds() + 59) / 60
+    }
+
+    /// Replaces the question with a new one of the same kind, or of another kind if the bank has none left.
+    pub fn renew_question(&mut self) {
+        self.nonce = rand::rng().random();
+        if !question::available(self.kind) {
+            self.kind = question::random_kind(0.0, 0.0);
+        }
+        match self.kind {
+            QuestionKind::Choice => {
+                let (title, options, correct_idx) = question::new_question();
+                self.title = title;
+                self.options = options;
+                self.correct = correct_idx;
+            }
+            QuestionKind::Text => {
+                let (title, answers) = question::new_text_question();
+                self.title = title;
+                self.answers = answers;
+            }
+            QuestionKind::Image => {
+                let (title, options, correct_idx) = question::captcha::new_image_question();
+                self.title = title;
+                self.options = options;
+                self.correct = correct_idx;
+            }
+        }
+    }
+
     pub fn message(&self) -> String {
+        if let Some(message) = handler::get(&self.handler).and_then(|handler| handler.message(self)) {
+            return message;
+        }
+        if self.token.is_some() {
+            return format!(
+                "{}，请在 {} 分钟内点击下方按钮，私聊机器人完成验证",
+                metion_user(&self.user),
+                self.left_minutes()
+            );
+        }
+        let muted = handler::get(&self.handler).is_some_and(|handler| handler.muted());
+        let hint = match self.kind {
+            QuestionKind::Choice | QuestionKind::Image => "",
+            QuestionKind::Text if self.private => "（直接发送答案）",
+            QuestionKind::Text if muted => "（请私聊机器人发送答案）",
+            QuestionKind::Text => "（直接发送答案，无法发言时请私聊机器人）",
+        };
         format!(
-            "{}，你有 {} 分钟时间回答以下问题：\n\n{}",
+            "{}，你有 {} 分钟时间回答以下问题{}：\n\n{}",
             metion_user(&self.user),
-            self.left_minutes,
+            self.left_minutes(),
+            hint,
             self.title
         )
     }
 
+    /// Callback data of a button of this question, `question` is set for buttons sent on another message.
+    pub fn callback_data(&self, action: &str, question: Option<MessageId>) -> String {
+        CallbackPayload {
+            handler: self.handler.clone(),
+            nonce: self.nonce,
+            question,
+            action: action.to_string(),
+        }
+        .encode()
+    }
+
+    /// Applies the handler patch, then encodes every callback button, including the ones the handler added. Admin
+    /// buttons are left out of the private chat, they stay on the group prompt instead.
+    fn patch_keyboard(&self, keyboard: InlineKeyboardMarkup, private: bool) -> InlineKeyboardMarkup {
+        let mut keyboard = match handler::get(&self.handler) {
+            Some(handler) => handler.keyboard_patch(keyboard),
+            None => keyboard,
+        };
+        if private {
+            for row in keyboard.inline_keyboard.iter_mut() {
+                row.retain(|button| {
+                    !matches!(&button.kind, InlineKeyboardButtonKind::CallbackData(action) if action.starts_with("admin"))
+                });
+            }
+            keyboard.inline_keyboard.retain(|row| !row.is_empty());
+        }
+        for button in keyboard.inline_keyboard.iter_mut().flatten() {
+            if let InlineKeyboardButtonKind::CallbackData(action) = &mut button.kind {
+                *action = self.callback_data(action, None);
+            }
+        }
+        keyboard
+    }
+
+    /// Keyboard of the group prompt once the question moved to the private chat, the buttons added by the handler
+    /// such as the admin ones.
+    pub fn prompt_keyboard(&self) -> InlineKeyboardMarkup {
+        self.patch_keyboard(InlineKeyboardMarkup::default(), false)
+    }
+
     pub fn keyboard(&self, change: bool) -> InlineKeyboardMarkup {
+        if let (Some(token), Some(me)) = (&self.token, crate::ME.get()) {
+            let url = Url::parse(&format!("https://t.me/{}?start=verify_{}", me.username(), token)).unwrap();
+            let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::url("开始验证🔐", url)]]);
+            return self.patch_keyboard(keyboard, self.private);
+        }
         let mut keyboard = InlineKeyboardMarkup::new(
             self.options
                 .iter()
                 .enumerate()
-                .map(|(idx, &text)| vec![InlineKeyboardButton::callback(text, idx.to_string())]),
+                .map(|(idx, text)| vec![InlineKeyboardButton::callback(text, idx.to_string())]),
         );
-        if change {
+        if self.kind == QuestionKind::Text {
+            if let Some(me) = crate::ME.get() {
+                let url = Url::parse(&format!("https://t.me/{}", me.username())).unwrap();
+                keyboard = keyboard.append_row(vec![InlineKeyboardButton::url("私聊回答💬", url)]);
+            }
+        }
+        if change && !self.options.is_empty() {
             keyboard = keyboard.append_row(vec![InlineKeyboardButton::callback("换题🔁", "change")])
         }
-        self.handler.keyboard_patch(keyboard)
+        self.patch_keyboard(keyboard, self.private)
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
-struct CasResult {
-    pub ok: bool,
+/// Sends the question, as a photo with caption for image questions.
+pub async fn send_question_message(bot: &Bot, data: &QuestionData, reply_to: Option<MessageId>) -> Result<Message> {
+    let msg = if data.kind == QuestionKind::Image && data.token.is_none() {
+        let photo = question::captcha::render(&data.options[data.correct])?;
+        let mut req = bot
+            .send_photo(data.question_chat(), InputFile::memory(photo))
+            .caption(data.message())
+            .parse_mode(ParseMode::Html)
+            .reply_markup(data.keyboard(true));
+        if let Some(id) = reply_to {
+            req = req.reply_parameters(ReplyParameters::new(id));
+        }
+        req.await?
+    } else {
+        let mut req = bot
+            .send_message(data.question_chat(), data.message())
+            .parse_mode(ParseMode::Html)
+            .reply_markup(data.keyboard(true));
+        if let Some(id) = reply_to {
+            req = req.reply_to_message_id(id);
+        }
+        req.await?
+    };
+    Ok(msg)
+}
+
+/// Updates the text or caption of the question message, `renewed` also replaces the captcha image.
+async fn edit_question_message(bot: &Bot, key: QuestionKey, data: &QuestionData, renewed: bool) -> Result<()> {
+    let (chat_id, msg_id) = key;
+    // the deep link prompt is plain text, whatever the question kind is
+    let kind = if data.token.is_some() {
+        QuestionKind::Choice
+    } else {
+        data.kind
+    };
+    match kind {
+        QuestionKind::Image if renewed => {
+            let photo = question::captcha::render(&data.options[data.correct])?;
+            let media = InputMediaPhoto::new(InputFile::memory(photo))
+                .caption(data.message())
+                .parse_mode(ParseMode::Html);
+            bot.edit_message_media(chat_id, msg_id, InputMedia::Photo(media))
+                .reply_markup(data.keyboard(false))
+                .await?;
+        }
+        QuestionKind::Image => {
+            bot.edit_message_caption(chat_id, msg_id)
+                .caption(data.message())
+                .parse_mode(ParseMode::Html)
+                .reply_markup(data.keyboard(false))
+                .await?;
+        }
+        _ => {
+            bot.edit_message_text(chat_id, msg_id, data.message())
+                .parse_mode(ParseMode::Html)
+                .reply_markup(data.keyboard(false))
+                .await?;
+        }
+    }
+    Ok(())
 }
 
 pub struct WatingManager {
-    // question message id as key
-    datas: BTreeMap<i32, QuestionData>,
-    handles: BTreeMap<i32, Vec<AbortHandle>>,
+    // `MessageId` is not `Ord`, a fixed hasher keeps the constructor const
+    datas: HashMap<QuestionKey, QuestionData, BuildHasherDefault<DefaultHasher>>,
 }
 
 impl WatingManager {
     pub const fn new() -> Self {
         Self {
-            datas: BTreeMap::new(),
-            handles: BTreeMap::new(),
+            datas: HashMap::with_hasher(BuildHasherDefault::new()),
         }
     }
 
-    pub async fn add(&mut self, question_id: i32, data: QuestionData) {
-        self.datas.insert(question_id, data);
-        self.handles.insert(question_id, vec![]);
+    fn insert(&mut self, key: QuestionKey, data: QuestionData) {
+        let next_tick = data.remaining().min(chrono::Duration::minutes(1));
+        scheduler::schedule(Job::Question(key.0, key.1), next_tick.to_std().unwrap_or_default());
+        index_text_question(&data, true);
+        if let Some(old) = self.datas.insert(key, data) {
+            index_text_question(&old, false);
+        }
     }
 
-    pub async fn finish(&mut self, question_id: i32) -> Option<(i32, QuestionData)> {
-        if let Some(handles) = self.handles.remove(&question_id) {
-            for handle in handles {
-                handle.abort();
+    /// Replaces a pending question, returns `false` if it is finished.
+    fn replace(&mut self, key: QuestionKey, data: &QuestionData) -> bool {
+        match self.datas.get_mut(&key) {
+            Some(stored) => {
+                index_text_question(stored, false);
+                index_text_question(data, true);
+                *stored = data.clone();
+                true
             }
+            None => false,
+        }
+    }
+
+    fn finish(&mut self, key: QuestionKey) -> Option<(QuestionKey, QuestionData)> {
+        scheduler::cancel(Job::Question(key.0, key.1));
+        let finished = self.datas.remove_entry(&key);
+        if let Some((_, data)) = &finished {
+            index_text_question(data, false);
         }
-        self.datas.remove_entry(&question_id)
+        finished
     }
+}
 
-    pub fn add_handle(&mut self, question_id: i32, handle: AbortHandle) {
-        if let Some(handles) = self.handles.get_mut(&question_id) {
-            handles.push(handle);
+// users answering a text question, so that the filter run on every message rarely takes the manager lock
+static TEXT_ANSWERERS: std::sync::Mutex<BTreeMap<UserId, usize>> = std::sync::Mutex::new(BTreeMap::new());
+
+fn index_text_question(data: &QuestionData, pending: bool) {
+    if data.kind != QuestionKind::Text {
+        return;
+    }
+    let mut answerers = TEXT_ANSWERERS.lock().unwrap();
+    let count = answerers.entry(data.user.id).or_default();
+    if pending {
+        *count += 1;
+    } else {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            answerers.remove(&data.user.id);
         }
     }
 }
@@ -108,29 +371,160 @@ impl Default for WatingManager {
     }
 }
 
-// question message id as key
-// static WATING_MESSAGES: Mutex<BTreeMap<i32, QuestionData>> = Mutex::const_new(BTreeMap::new());
-// static WATING_MESSAGES_TASKS: Mutex<BTreeMap<i32, Vec<AbortHandle>>> =
-//     Mutex::const_new(BTreeMap::new());
+// only held to access the map, Redis and Telegram are called on copies so that a slow request blocks no other question
 static WATING_MANAGER: Mutex<WatingManager> = Mutex::const_new(WatingManager::new());
 
 static TO_DELETE_MESSAGE: SegQueue<(ChatId, MessageId)> = SegQueue::new();
 
-pub async fn add_wating_user(question_id: MessageId, data: QuestionData) {
-    WATING_MANAGER.lock().await.add(question_id.0, data).await;
+fn question_field((chat_id, msg_id): QuestionKey) -> String {
+    format!("{}/{}", chat_id, msg_id)
 }
 
-pub async fn get_data_by_msg(msg_id: &i32) -> Option<MappedMutexGuard<'static, QuestionData>> {
-    let users = WATING_MANAGER.lock().await;
-    MutexGuard::try_map(users, |users| users.datas.get_mut(msg_id)).ok()
+async fn save_question(key: QuestionKey, data: &QuestionData) -> Result<()> {
+    let mut con = crate::get_connection().await;
+    () = con
+        .hset(WATING_QUESTIONS_KEY, question_field(key), serde_json::to_string(data)?)
+        .await?;
+    Ok(())
 }
 
-pub async fn user_finish(msg_id: MessageId) -> Option<(i32, QuestionData)> {
-    WATING_MANAGER.lock().await.finish(msg_id.0).await
+pub async fn add_wating_user(key: QuestionKey, data: QuestionData) -> Result<()> {
+    save_question(key, &data).await?;
+    WATING_MANAGER.lock().await.insert(key, data);
+    Ok(())
 }
 
-pub async fn add_wating_handle(msg_id: MessageId, handle: AbortHandle) {
-    WATING_MANAGER.lock().await.add_handle(msg_id.0, handle);
+/// Stores the changed copy of a pending question in memory and in Redis, nothing is done once it is finished.
+pub async fn update_wating_user(key: QuestionKey, data: &QuestionData) {
+    if !WATING_MANAGER.lock().await.replace(key, data) {
+        return;
+    }
+    if let Err(err) = save_question(key, data).await {
+        log::error!("Failed to save question {}: {}", question_field(key), err);
+    }
+    // finished while saving, the saved copy would be resumed on the next start
+    if !WATING_MANAGER.lock().await.datas.contains_key(&key) {
+        let mut con = crate::get_connection().await;
+        let res: redis::RedisResult<()> = con.hdel(WATING_QUESTIONS_KEY, question_field(key)).await;
+        if let Err(err) = res {
+            log::error!("Redis error: {}", err);
+        }
+    }
+}
+
+/// Registers the built-in handlers, must be called before questions are resumed.
+pub fn register_handlers() {
+    handler::register(join_handler::JoinHandler);
+    handler::register(link_handler::LinkHandler);
+    handler::register(join_request_handler::JoinRequestHandler);
+    handler::register(test_handler::TestHandler);
+    handler::register(channel_handler::ChannelHandler);
+}
+
+/// Restores the pending questions saved in Redis, resumes their countdown or times them out. A broken entry is
+/// logged and skipped, the others are still resumed.
+pub async fn resume_wating_users(bot: Bot) -> Result<()> {
+    let saved: Vec<(String, String)> = {
+        let mut con = crate::get_connection().await;
+        con.hgetall(WATING_QUESTIONS_KEY).await?
+    };
+
+    for (field, raw) in saved {
+        if let Err(err) = resume_question(&bot, &field, &raw).await {
+            log::error!("Failed to resume question {}: {}", field, err);
+        }
+    }
+
+    while let Some((chat, msg)) = TO_DELETE_MESSAGE.pop() {
+        bot.delete_message(chat, msg).await.ok();
+    }
+    Ok(())
+}
+
+async fn resume_question(bot: &Bot, field: &str, raw: &str) -> Result<()> {
+    let data = match serde_json::from_str::<QuestionData>(raw) {
+        Ok(data) => data,
+        Err(err) => {
+            log::error!("Dropping broken question {}: {}", field, err);
+            let mut con = crate::get_connection().await;
+            () = con.hdel(WATING_QUESTIONS_KEY, field).await?;
+            return Ok(());
+        }
+    };
+    let (chat_id, msg_id) = field
+        .split_once('/')
+        .ok_or_else(|| anyhow::anyhow!("invalid question field"))?;
+    let key = (ChatId(chat_id.parse()?), MessageId(msg_id.parse()?));
+
+    if data.deadline <= Utc::now() {
+        let mut con = crate::get_connection().await;
+        () = con.hdel(WATING_QUESTIONS_KEY, field).await?;
+        handler::get_or_err(&data.handler)?
+            .handle_timeout(bot.clone(), (key, data))
+            .await
+    } else {
+        WATING_MANAGER.lock().await.insert(key, data);
+        Ok(())
+    }
+}
+
+/// Copy of a pending question, the lock is released at once so that the caller can do I/O. Store the changes with
+/// [`update_wating_user`].
+pub async fn get_data_by_msg(key: &QuestionKey) -> Option<QuestionData> {
+    WATING_MANAGER.lock().await.datas.get(key).cloned()
+}
+
+/// Snapshot of the questions pending in `handler`.
+pub async fn pending_questions(handler: &str) -> Vec<(QuestionKey, QuestionData)> {
+    let manager = WATING_MANAGER.lock().await;
+    manager
+        .datas
+        .iter()
+        .filter(|(_, data)| data.handler == handler)
+        .map(|(&key, data)| (key, data.clone()))
+        .collect()
+}
+
+/// Questions pending for `user_id` in the group `chat_id`, including the ones asked in private chat.
+pub async fn questions_of_user(chat_id: ChatId, user_id: UserId) -> Vec<QuestionKey> {
+    let manager = WATING_MANAGER.lock().await;
+    manager
+        .datas
+        .iter()
+        .filter(|(_, data)| data.chat_id == chat_id && data.user.id == user_id)
+        .map(|(&key, _)| key)
+        .collect()
+}
+
+/// Finds the question waiting for `user_id` to open the private chat with the deep link `token`.
+pub async fn find_by_token(token: &str, user_id: UserId) -> Option<QuestionKey> {
+    let manager = WATING_MANAGER.lock().await;
+    manager
+        .datas
+        .iter()
+        .find(|(_, data)| data.user.id == user_id && data.token.as_deref() == Some(token))
+        .map(|(&key, _)| key)
+}
+
+/// Key of the question moved to the private chat from the group prompt `key`, the admin buttons left in the group
+/// point to the prompt.
+async fn moved_question(key: QuestionKey) -> Option<QuestionKey> {
+    let manager = WATING_MANAGER.lock().await;
+    manager
+        .datas
+        .iter()
+        .find(|(_, data)| data.private && data.chat_id == key.0 && data.prompt == Some(key.1))
+        .map(|(&key, _)| key)
+}
+
+pub async fn user_finish(key: QuestionKey) -> Option<(QuestionKey, QuestionData)> {
+    let finished = WATING_MANAGER.lock().await.finish(key);
+    let mut con = crate::get_connection().await;
+    let res: redis::RedisResult<()> = con.hdel(WATING_QUESTIONS_KEY, question_field(key)).await;
+    if let Err(err) = res {
+        log::error!("Redis error: {}", err);
+    }
+    finished
 }
 
 pub struct CallbackResult {
@@ -151,12 +545,23 @@ pub async fn callback(bot: Bot, callback: CallbackQuery) -> Result<()> {
         bot.answer_callback_query(callback.id).await?;
         return Ok(());
     }
-    let msg_id = callback.message.as_ref().unwrap().id();
+    let origin = callback.message.as_ref().unwrap();
+    let payload = if let Some(payload) = CallbackPayload::parse(callback.data.as_ref().unwrap()) {
+        payload
+    } else {
+        bot.answer_callback_query(callback.id)
+            .text("按钮已失效")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    };
+    let key = (origin.chat().id, payload.question.unwrap_or(origin.id()));
+    let key = moved_question(key).await.unwrap_or(key);
     let callback_id = callback.id.clone();
 
-    let (result, mut handler, user_id) = {
-        if let Some(mut data) = get_data_by_msg(&msg_id.0).await {
-            let result = callback_handle(bot.clone(), &callback, data.deref_mut()).await?;
+    let (result, handler) = {
+        if let Some(mut data) = get_data_by_msg(&key).await {
+            let result = callback_handle(bot.clone(), &callback, &payload, key, &mut data).await?;
 
             if result.typ == Answer {
                 if let Some(ref msg) = result.msg {
@@ -168,24 +573,22 @@ pub async fn callback(bot: Bot, callback: CallbackQuery) -> Result<()> {
                     bot.answer_callback_query(callback.id).await?;
                 }
             }
-            (result, data.handler, data.user.id)
+            (result, data.handler.clone())
         } else {
-            bot.answer_callback_query(callback.id).await?;
+            bot.answer_callback_query(callback.id)
+                .text("该验证已结束")
+                .show_alert(true)
+                .await?;
             return Ok(());
         }
     };
 
     let bot2 = bot.clone();
     use CallbackResultType::*;
-    let res = match result.typ {
-        Answer => return Ok(()),
-        HandleCorrect => {
-            auth_database::add_authed(user_id.0).await?;
-            handler.handle_correct(bot, msg_id).await
-        }
-        HandleWrong => handler.handle_wrong(bot, msg_id).await,
-        HandleOther => handler.handle_other(bot, callback.data.as_ref().unwrap(), msg_id).await,
-    };
+    if result.typ == Answer {
+        return Ok(());
+    }
+    let res = handle_result(bot, &handler, key, result.typ, &payload.action).await;
     let res = match res {
         Ok(res) => res,
         Err(err) => {
@@ -207,7 +610,30 @@ pub async fn callback(bot: Bot, callback: CallbackQuery) -> Result<()> {
     Ok(())
 }
 
-async fn callback_handle(bot: Bot, callback: &CallbackQuery, data: &mut QuestionData) -> Result<CallbackResult> {
+async fn handle_result(
+    bot: Bot,
+    handler: &str,
+    key: QuestionKey,
+    typ: CallbackResultType,
+    word: &str,
+) -> Result<Option<String>> {
+    use CallbackResultType::*;
+    let handler = handler::get_or_err(handler)?;
+    match typ {
+        Answer => Ok(None),
+        HandleCorrect => handler.handle_correct(bot, key).await,
+        HandleWrong => handler.handle_wrong(bot, key).await,
+        HandleOther => handler.handle_other(bot, word, key).await,
+    }
+}
+
+async fn callback_handle(
+    bot: Bot,
+    callback: &CallbackQuery,
+    payload: &CallbackPayload,
+    key: QuestionKey,
+    data: &mut QuestionData,
+) -> Result<CallbackResult> {
     use CallbackResultType::*;
 
     macro_rules! res {
@@ -231,12 +657,18 @@ async fn callback_handle(bot: Bot, callback: &CallbackQuery, data: &mut Question
         };
     }
 
-    let origin = callback.message.as_ref().unwrap();
-    let callback_data = callback.data.as_ref().unwrap();
+    if payload.handler != data.handler {
+        return res!(Answer, "此按钮不属于当前验证");
+    }
+    let callback_data = &payload.action;
+    // admin actions do not depend on the question, so buttons like the CAS warning survive a change
+    if !callback_data.starts_with("admin") && payload.nonce != data.nonce {
+        return res!(Answer, "题目已更换，请使用最新的按钮");
+    }
 
     if callback_data.starts_with("admin") {
         let res: std::result::Result<ChatMember, teloxide::RequestError> =
-            bot.get_chat_member(origin.chat().id, callback.from.id).await;
+            bot.get_chat_member(data.chat_id, callback.from.id).await;
         let member: ChatMember = match res {
             Ok(member) => member,
             Err(err) => {
@@ -250,6 +682,8 @@ async fn callback_handle(bot: Bot, callback: &CallbackQuery, data: &mut Question
         }
     }
 
+    // options are numbered, other actions are up to the handler
+    let is_option = callback_data.parse::<usize>().is_ok();
     if callback.from.id != data.user.id {
         return res!(
             Answer,
@@ -258,6 +692,8 @@ async fn callback_handle(bot: Bot, callback: &CallbackQuery, data: &mut Question
                     "回答正确！但是并不会奖励屎给你。"
                 } else if callback_data == "change" {
                     "不会就别点！"
+                } else if !is_option {
+                    "这不是你的按钮"
                 } else {
                     "回答错误！"
                 }
@@ -266,49 +702,106 @@ async fn callback_handle(bot: Bot, callback: &CallbackQuery, data: &mut Question
         );
     }
 
-    if callback_data == &data.correct.to_string() {
+    if data.kind != QuestionKind::Text && callback_data == &data.correct.to_string() {
         res!(HandleCorrect)
     } else if callback_data == "change" {
-        let (title, options, correct_idx) = question::new_question();
-        data.correct = correct_idx;
-        data.options = options;
-        data.title = title;
-        bot.edit_message_text(origin.chat().id, origin.id(), data.message())
-            .parse_mode(ParseMode::Html)
-            .reply_markup(data.keyboard(false))
-            .await?;
+        data.renew_question();
+        edit_question_message(&bot, key, data, true).await?;
+        update_wating_user(key, data).await;
+        res!(Answer)
+    } else if !is_option {
+        res!(HandleOther)
+    } else if data.kind == QuestionKind::Text {
         res!(Answer)
     } else {
         res!(HandleWrong)
     }
 }
 
-async fn waiting_answer<Fn, F>(bot: Bot, msg_id: MessageId, timeout: Fn)
-where
-    Fn: FnOnce((i32, QuestionData)) -> F + Send + 'static,
-    F: Future<Output = ()>,
-{
-    loop {
-        sleep(std::time::Duration::from_secs(60)).await;
-
-        if let Some(mut data) = get_data_by_msg(&msg_id.0).await {
-            data.left_minutes -= 1;
-            if data.left_minutes == 0 {
-                break;
-            } else {
-                bot.edit_message_text(data.chat_id, msg_id, data.message())
-                    .parse_mode(ParseMode::Html)
-                    .reply_markup(data.keyboard(false))
-                    .await
-                    .ok();
-            }
+/// Finds the pending text question answered by a message, sent in its group or in the private chat with the bot.
+pub async fn find_text_question(msg: &Message) -> Option<QuestionKey> {
+    let user = msg.from.as_ref()?;
+    if msg.text()?.starts_with('/') || !TEXT_ANSWERERS.lock().unwrap().contains_key(&user.id) {
+        return None;
+    }
+    let manager = WATING_MANAGER.lock().await;
+    manager
+        .datas
+        .iter()
+        .find(|(_, data)| {
+            data.kind == QuestionKind::Text
+                && data.token.is_none()
+                && data.user.id == user.id
+                && (data.chat_id == msg.chat.id || msg.chat.is_private())
+        })
+        .map(|(&key, _)| key)
+}
+
+pub async fn text_answer(bot: Bot, msg: Message) -> Result<()> {
+    use CallbackResultType::*;
+
+    let key = if let Some(key) = find_text_question(&msg).await {
+        key
+    } else {
+        return Ok(());
+    };
+    bot.delete_message(msg.chat.id, msg.id).await.ok();
+
+    let (typ, handler, user) = if let Some(data) = get_data_by_msg(&key).await {
+        let typ = if question::check_answer(msg.text().unwrap_or_default(), &data.answers) {
+            HandleCorrect
         } else {
-            break;
+            HandleWrong
+        };
+        (typ, data.handler.clone(), data.user.clone())
+    } else {
+        return Ok(());
+    };
+
+    let res = handle_result(bot.clone(), &handler, key, typ, "").await?;
+    if let Some(text) = res {
+        let sent = bot
+            .send_message(msg.chat.id, format!("{}，{}", metion_user(&user), text))
+            .parse_mode(ParseMode::Html)
+            .await?;
+        if !msg.chat.is_private() {
+            scheduler::schedule(
+                Job::DeleteMessage(msg.chat.id, sent.id),
+                std::time::Duration::from_secs(10),
+            );
         }
     }
+    while let Some((chat, msg)) = TO_DELETE_MESSAGE.pop() {
+        bot.delete_message(chat, msg).await.ok();
+    }
+    Ok(())
+}
 
-    if let Some(data) = user_finish(msg_id).await {
-        timeout(data).await;
+/// Fired by the scheduler every minute of a pending question, updates the countdown or times it out.
+async fn countdown(bot: Bot, key: QuestionKey) {
+    let data = match get_data_by_msg(&key).await {
+        Some(data) => data,
+        None => return,
+    };
+
+    let remaining = data.remaining();
+    if !remaining.is_zero() {
+        scheduler::schedule(
+            Job::Question(key.0, key.1),
+            remaining.min(chrono::Duration::minutes(1)).to_std().unwrap_or_default(),
+        );
+        edit_question_message(&bot, key, &data, false).await.ok();
+        return;
+    }
+
+    if let Some(data) = user_finish(key).await {
+        let res = match handler::get_or_err(&data.1.handler) {
+            Ok(handler) => handler.handle_timeout(bot.clone(), data).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = res {
+            log::error!("Failed to time out question {}: {}", question_field(key), err);
+        }
     }
     while let Some((chat, msg)) = TO_DELETE_MESSAGE.pop() {
         bot.delete_message(chat, msg).await.ok();
@@ -1,16 +1,248 @@
+use std::{collections::HashMap, fmt};
+
 use anyhow::Result;
+use chrono::{DateTime, Local, Utc};
 use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, UserId};
+
+use crate::CONFIG;
 
+/// the global scope, chat scopes are suffixed with `:{chat_id}`
 pub const AUTHED_USERS_KEY: &str = "shit_bot_authed_users";
+/// metadata of the authed users, users authed before it existed have none
+const AUTH_INFO_KEY: &str = "shit_bot_authed_info";
+/// chats having their own scope
+const SCOPES_KEY: &str = "shit_bot_auth_scopes";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// passing the verification in these chats trusts the user everywhere, other chats only trust their own users
+    pub global_chats: Vec<ChatId>,
+}
+
+/// Where an auth is valid, users in the global scope are authed in every chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Chat(ChatId),
+}
+
+impl Scope {
+    /// Scope of the auth earned in `chat_id`.
+    pub fn of(chat_id: ChatId) -> Self {
+        if CONFIG.get().unwrap().auth.global_chats.contains(&chat_id) {
+            Scope::Global
+        } else {
+            Scope::Chat(chat_id)
+        }
+    }
+
+    /// `global` or a chat id, as given to the admin commands.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "global" => Some(Scope::Global),
+            s => s.parse().ok().map(|chat_id| Scope::of(ChatId(chat_id))),
+        }
+    }
+
+    /// Scope of the managed chat, used when none is given.
+    pub fn main() -> Self {
+        Scope::of(CONFIG.get().unwrap().manage_chat)
+    }
+
+    fn users_key(&self) -> String {
+        match self {
+            Scope::Global => AUTHED_USERS_KEY.to_string(),
+            Scope::Chat(chat_id) => format!("{}:{}", AUTHED_USERS_KEY, chat_id),
+        }
+    }
+
+    fn info_key(&self) -> String {
+        match self {
+            Scope::Global => AUTH_INFO_KEY.to_string(),
+            Scope::Chat(chat_id) => format!("{}:{}", AUTH_INFO_KEY, chat_id),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Global => f.write_str("全局"),
+            Scope::Chat(chat_id) => write!(f, "群组 {}", chat_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    /// answered a question
+    Quiz,
+    /// allowed by an admin, with the button or `/auth`
+    AdminAllow,
+    Premium,
+    /// imported from a backup or another bot
+    Import,
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuthMethod::Quiz => "答题验证",
+            AuthMethod::AdminAllow => "管理员允许",
+            AuthMethod::Premium => "Premium 用户",
+            AuthMethod::Import => "导入",
+            AuthMethod::Unknown => "未知",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthInfo {
+    pub time: Option<DateTime<Utc>>,
+    pub method: AuthMethod,
+    /// chat the user was verified in
+    pub chat_id: Option<ChatId>,
+    /// admin who authed the user
+    pub by: Option<UserId>,
+}
 
-pub async fn is_authed(user_id: u64) -> Result<bool> {
+impl AuthInfo {
+    pub fn new(method: AuthMethod, chat_id: Option<ChatId>) -> Self {
+        Self {
+            time: Some(Utc::now()),
+            method,
+            chat_id,
+            by: None,
+        }
+    }
+
+    pub fn by(self, admin: UserId) -> Self {
+        Self {
+            by: Some(admin),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for AuthInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "方式：{}", self.method)?;
+        if let Some(time) = self.time {
+            write!(f, "\n时间：{}", time.with_timezone(&Local).format("%Y-%m-%d %H:%M"))?;
+        }
+        if let Some(chat_id) = self.chat_id {
+            write!(f, "\n群组：{}", chat_id)?;
+        }
+        if let Some(by) = self.by {
+            write!(f, "\n操作者：{}", by)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the user is trusted in `chat_id`, by the global scope or the one of the chat.
+pub async fn is_authed(user_id: UserId, chat_id: ChatId) -> Result<bool> {
     let mut con = crate::get_connection().await;
-    Ok(con.sismember(AUTHED_USERS_KEY, user_id).await?)
+    let (global, chat): (bool, bool) = redis::pipe()
+        .sismember(Scope::Global.users_key(), user_id.0)
+        .sismember(Scope::Chat(chat_id).users_key(), user_id.0)
+        .query_async(&mut con)
+        .await?;
+    Ok(global || chat)
 }
 
-pub async fn add_authed(user_id: u64) -> Result<()> {
+pub async fn add_authed(user_id: UserId, scope: Scope, info: AuthInfo) -> Result<()> {
     let mut con = crate::get_connection().await;
-    () = con.sadd(AUTHED_USERS_KEY, user_id).await?;
+    () = con.sadd(scope.users_key(), user_id.0).await?;
+    () = con
+        .hset(scope.info_key(), user_id.0, serde_json::to_string(&info)?)
+        .await?;
+    if let Scope::Chat(chat_id) = scope {
+        () = con.sadd(SCOPES_KEY, chat_id.0).await?;
+    }
 
     Ok(())
 }
+
+/// Revokes the auth in `scope`, returns `false` if the user was not authed there.
+pub async fn remove_authed(user_id: UserId, scope: Scope) -> Result<bool> {
+    let mut con = crate::get_connection().await;
+    let removed: bool = con.srem(scope.users_key(), user_id.0).await?;
+    () = con.hdel(scope.info_key(), user_id.0).await?;
+
+    Ok(removed)
+}
+
+/// The global scope and the chats having their own.
+pub async fn scopes() -> Result<Vec<Scope>> {
+    let mut con = crate::get_connection().await;
+    let mut chats: Vec<i64> = con.smembers(SCOPES_KEY).await?;
+    chats.sort_unstable();
+    Ok(std::iter::once(Scope::Global)
+        .chain(chats.into_iter().map(|chat_id| Scope::Chat(ChatId(chat_id))))
+        .collect())
+}
+
+/// How the user was authed in each scope trusting them.
+pub async fn auth_info(user_id: UserId) -> Result<Vec<(Scope, AuthInfo)>> {
+    let mut infos = Vec::new();
+    for scope in scopes().await? {
+        let mut con = crate::get_connection().await;
+        if !con.sismember(scope.users_key(), user_id.0).await? {
+            continue;
+        }
+        let raw: Option<String> = con.hget(scope.info_key(), user_id.0).await?;
+        let info = match raw {
+            Some(raw) => serde_json::from_str(&raw)?,
+            None => AuthInfo::default(),
+        };
+        infos.push((scope, info));
+    }
+    Ok(infos)
+}
+
+/// Every user authed in `scope` with the metadata, sorted by id.
+pub async fn all_authed(scope: Scope) -> Result<Vec<(UserId, AuthInfo)>> {
+    let mut con = crate::get_connection().await;
+    let mut users: Vec<u64> = con.smembers(scope.users_key()).await?;
+    let infos: HashMap<u64, String> = con.hgetall(scope.info_key()).await?;
+    users.sort_unstable();
+    Ok(users
+        .into_iter()
+        .map(|user_id| {
+            let info = infos
+                .get(&user_id)
+                .and_then(|raw| serde_json::from_str(raw).ok())
+                .unwrap_or_default();
+            (UserId(user_id), info)
+        })
+        .collect())
+}
+
+/// Adds the users to `scope` in one go, keeping the metadata of the ones already authed there. Returns the number of
+/// new users.
+pub async fn import(scope: Scope, users: &[(UserId, AuthInfo)]) -> Result<usize> {
+    let mut new = 0;
+    let mut con = crate::get_connection().await;
+    if let Scope::Chat(chat_id) = scope {
+        () = con.sadd(SCOPES_KEY, chat_id.0).await?;
+    }
+    for chunk in users.chunks(1000) {
+        let mut pipe = redis::pipe();
+        for (user_id, info) in chunk {
+            pipe.sadd(scope.users_key(), user_id.0)
+                .hset_nx(scope.info_key(), user_id.0, serde_json::to_string(info)?)
+                .ignore();
+        }
+        let added: Vec<usize> = pipe.query_async(&mut con).await?;
+        new += added.iter().sum::<usize>();
+    }
+    Ok(new)
+}
@@ -0,0 +1,284 @@
+//! Import and export of the authed users as JSON or CSV, and import from Telegram Desktop chat exports.
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use teloxide::{
+    net::Download,
+    payloads::SendDocumentSetters,
+    prelude::*,
+    types::{ChatId, InputFile, ReplyParameters, UserId},
+};
+
+use super::auth_database::{self, AuthInfo, AuthMethod, Scope};
+use crate::{Bot, utils::EasySendMessage};
+
+const CLI_USAGE: &str = "usage: shit_bot import-auth|export-auth <file> [global|<chat id>], the format follows the extension and \
+                         the scope defaults to the one of manage_chat";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// also reads the `result.json` of Telegram Desktop
+    Json,
+    Csv,
+}
+
+impl Format {
+    /// Guesses the format from the file name, JSON unless it ends with `.csv`.
+    pub fn from_file_name(name: &str) -> Self {
+        if name.to_lowercase().ends_with(".csv") {
+            Format::Csv
+        } else {
+            Format::Json
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    user_id: UserId,
+    #[serde(flatten)]
+    info: AuthInfo,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    Id(UserId),
+    Record(Record),
+}
+
+const CSV_HEADER: &str = "user_id,method,time,chat_id,by";
+
+pub fn export(users: &[(UserId, AuthInfo)], format: Format) -> Result<Vec<u8>> {
+    match format {
+        Format::Json => {
+            let records = users
+                .iter()
+                .map(|(user_id, info)| Record {
+                    user_id: *user_id,
+                    info: info.clone(),
+                })
+                .collect::<Vec<_>>();
+            Ok(serde_json::to_vec_pretty(&records)?)
+        }
+        Format::Csv => {
+            let mut csv = String::from(CSV_HEADER);
+            for (user_id, info) in users {
+                csv += &format!(
+                    "\n{},{},{},{},{}",
+                    user_id,
+                    serde_json::to_value(info.method)?.as_str().unwrap_or_default(),
+                    info.time.map(|time| time.to_rfc3339()).unwrap_or_default(),
+                    info.chat_id.map(|chat_id| chat_id.to_string()).unwrap_or_default(),
+                    info.by.map(|by| by.to_string()).unwrap_or_default(),
+                );
+            }
+            csv.push('\n');
+            Ok(csv.into_bytes())
+        }
+    }
+}
+
+/// Users without metadata, e.g. from a plain id list or a chat export, are marked as imported now.
+pub fn parse(data: &[u8], format: Format) -> Result<Vec<(UserId, AuthInfo)>> {
+    let users = match format {
+        Format::Json => parse_json(data)?,
+        Format::Csv => parse_csv(std::str::from_utf8(data)?)?,
+    };
+    Ok(users
+        .into_iter()
+        .map(|(user_id, info)| {
+            if info.time.is_none() && info.method == AuthMethod::Unknown {
+                (user_id, AuthInfo::new(AuthMethod::Import, info.chat_id))
+            } else {
+                (user_id, info)
+            }
+        })
+        .collect())
+}
+
+fn parse_json(data: &[u8]) -> Result<Vec<(UserId, AuthInfo)>> {
+    let value: Value = serde_json::from_slice(data)?;
+    if value.is_object() {
+        return Ok(parse_telegram_export(&value)
+            .into_iter()
+            .map(|user_id| (user_id, AuthInfo::default()))
+            .collect());
+    }
+    let entries: Vec<Entry> = serde_json::from_value(value)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            Entry::Id(user_id) => (user_id, AuthInfo::default()),
+            Entry::Record(record) => (record.user_id, record.info),
+        })
+        .collect())
+}
+
+/// Authors of the messages in a `result.json`, of a single chat or of the whole account.
+fn parse_telegram_export(export: &Value) -> Vec<UserId> {
+    let single = export.get("messages").into_iter();
+    let chats = export
+        .pointer("/chats/list")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|chat| chat.get("messages"));
+    let mut users = single
+        .chain(chats)
+        .filter_map(Value::as_array)
+        .flatten()
+        .filter_map(|message| message.get("from_id")?.as_str()?.strip_prefix("user")?.parse().ok())
+        .map(UserId)
+        .collect::<Vec<_>>();
+    users.sort_unstable();
+    users.dedup();
+    users
+}
+
+fn parse_csv(data: &str) -> Result<Vec<(UserId, AuthInfo)>> {
+    let mut users = Vec::new();
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (idx == 0 && line.starts_with("user_id")) {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let field = |i: usize| fields.get(i).copied().filter(|field| !field.is_empty());
+        let parse_err = |what: &str| anyhow!("第 {} 行的 {} 无效：{}", idx + 1, what, line);
+
+        let user_id = field(0)
+            .and_then(|id| id.parse().ok())
+            .ok_or_else(|| parse_err("user_id"))?;
+        let method = match field(1) {
+            Some(method) => {
+                serde_json::from_value(Value::String(method.to_string())).map_err(|_| parse_err("method"))?
+            }
+            None => AuthMethod::Unknown,
+        };
+        let time = match field(2) {
+            Some(time) => Some(time.parse().map_err(|_| parse_err("time"))?),
+            None => None,
+        };
+        let chat_id = match field(3) {
+            Some(chat_id) => Some(ChatId(chat_id.parse().map_err(|_| parse_err("chat_id"))?)),
+            None => None,
+        };
+        let by = match field(4) {
+            Some(by) => Some(UserId(by.parse().map_err(|_| parse_err("by"))?)),
+            None => None,
+        };
+        users.push((
+            UserId(user_id),
+            AuthInfo {
+                time,
+                method,
+                chat_id,
+                by,
+            },
+        ));
+    }
+    Ok(users)
+}
+
+/// `shit_bot import-auth <file> [scope]` and `shit_bot export-auth <file> [scope]`, run instead of the bot.
+pub async fn run_cli(args: &[String]) -> Result<()> {
+    let (command, path, scope) = match args {
+        [command, path] => (command, path, Scope::main()),
+        [command, path, scope] => (command, path, Scope::parse(scope).ok_or_else(|| anyhow!(CLI_USAGE))?),
+        _ => return Err(anyhow!(CLI_USAGE)),
+    };
+    match command.as_str() {
+        "import-auth" => {
+            let data = tokio::fs::read(path).await?;
+            let users = parse(&data, Format::from_file_name(path))?;
+            let new = auth_database::import(scope, &users).await?;
+            println!(
+                "Imported {} users from {} into {:?}, {} new",
+                users.len(),
+                path,
+                scope,
+                new
+            );
+        }
+        "export-auth" => {
+            let users = auth_database::all_authed(scope).await?;
+            tokio::fs::write(path, export(&users, Format::from_file_name(path))?).await?;
+            println!("Exported {} users of {:?} to {}", users.len(), scope, path);
+        }
+        _ => return Err(anyhow!(CLI_USAGE)),
+    }
+    Ok(())
+}
+
+/// Imports the document the command replies to into `scope`, the one of `manage_chat` by default.
+pub async fn import_command(bot: Bot, message: &Message, scope: Option<Scope>) -> Result<()> {
+    let scope = scope.unwrap_or_else(Scope::main);
+    let document = if let Some(document) = message.reply_to_message().and_then(|reply| reply.document()) {
+        document
+    } else {
+        bot.send_message(
+            message.chat.id,
+            "请回复一个 JSON、CSV 文件或 Telegram Desktop 导出的 result.json",
+        )
+        .reply_to_message_id(message.id)
+        .await?;
+        return Ok(());
+    };
+    let file = bot.get_file(document.file.id.clone()).await?;
+    let mut data = Vec::new();
+    bot.download_file(&file.path, &mut data).await?;
+
+    let format = Format::from_file_name(document.file_name.as_deref().unwrap_or_default());
+    let text = match parse(&data, format) {
+        Ok(users) => {
+            let new = auth_database::import(scope, &users).await?;
+            format!("已导入 {} 个用户（{}），其中 {} 个为新用户", users.len(), scope, new)
+        }
+        Err(err) => format!("导入失败：{}", err),
+    };
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .await?;
+    Ok(())
+}
+
+/// Sends the export to the admin in private chat, the list must not be posted in the group.
+pub async fn export_command(bot: Bot, message: &Message, format: &str, scope: Option<Scope>) -> Result<()> {
+    let admin = if let Some(admin) = message.from.as_ref() {
+        admin.id
+    } else {
+        return Ok(());
+    };
+    let scope = scope.unwrap_or_else(Scope::main);
+    let format = if format.trim().eq_ignore_ascii_case("csv") {
+        Format::Csv
+    } else {
+        Format::Json
+    };
+    let users = auth_database::all_authed(scope).await?;
+    let file = InputFile::memory(export(&users, format)?).file_name(format!("authed_users.{}", format.extension()));
+    let mut req = bot
+        .send_document(admin, file)
+        .caption(format!("共 {} 个已验证用户（{}）", users.len(), scope));
+    if message.chat.is_private() {
+        req = req.reply_parameters(ReplyParameters::new(message.id));
+    }
+    let text = match req.await {
+        Ok(_) if message.chat.is_private() => return Ok(()),
+        Ok(_) => "已私聊发送导出文件".to_string(),
+        Err(err) => format!("私聊发送失败，请先私聊机器人：{}", err),
+    };
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Callback payload of the question buttons, tying a press to the question version it was rendered for.
+
+use teloxide::types::MessageId;
+
+// Telegram rejects longer callback data
+const MAX_LEN: usize = 64;
+
+/// Encoded as `handler:nonce:question:action`, with `-` for the question message itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallbackPayload {
+    pub handler: String,
+    pub nonce: u32,
+    /// question message, for buttons sent on another message such as the CAS warning
+    pub question: Option<MessageId>,
+    pub action: String,
+}
+
+impl CallbackPayload {
+    pub fn encode(&self) -> String {
+        let question = match self.question {
+            Some(id) => id.0.to_string(),
+            None => "-".to_string(),
+        };
+        let data = format!("{}:{:08x}:{}:{}", self.handler, self.nonce, question, self.action);
+        if data.len() > MAX_LEN {
+            log::warn!("Callback data {} is longer than {} bytes", data, MAX_LEN);
+        }
+        data
+    }
+
+    pub fn parse(data: &str) -> Option<Self> {
+        let mut parts = data.splitn(4, ':');
+        let handler = parts.next()?.to_string();
+        let nonce = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let question = match parts.next()? {
+            "-" => None,
+            id => Some(MessageId(id.parse().ok()?)),
+        };
+        let action = parts.next()?.to_string();
+        Some(Self {
+            handler,
+            nonce,
+            question,
+            action,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(question: Option<MessageId>, action: &str) -> CallbackPayload {
+        CallbackPayload {
+            handler: "Join".to_string(),
+            nonce: 0xdead_beef,
+            question,
+            action: action.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for payload in [payload(None, "2"), payload(Some(MessageId(42)), "admin-ban")] {
+            assert_eq!(CallbackPayload::parse(&payload.encode()), Some(payload));
+        }
+        assert_eq!(payload(None, "change").encode(), "Join:deadbeef:-:change");
+    }
+
+    #[test]
+    fn action_keeps_separators() {
+        let payload = payload(None, "joined:extra");
+        assert_eq!(CallbackPayload::parse(&payload.encode()), Some(payload));
+    }
+
+    #[test]
+    fn rejects_malformed_data() {
+        for data in [
+            "",
+            "Join",
+            "Join:zzzzzzzz:-:1",
+            "Join:1ffffffff:-:1",
+            "Join:00000001:abc:1",
+            "Join:00000001:-",
+            // buttons sent before the payload was encoded
+            "admin-ban",
+        ] {
+            assert_eq!(CallbackPayload::parse(data), None, "{}", data);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use reqwest::Url;
+use teloxide::{
+    prelude::*,
+    types::{Chat, ChatPermissions, InlineKeyboardButton, InlineKeyboardMarkup, Message, User},
+};
+
+use super::{
+    QuestionData, QuestionKey, get_data_by_msg,
+    handler::{Handler, res},
+    policy::{ChannelMode, ChannelPolicy, RequiredChannel},
+    user_finish,
+};
+use crate::{Bot, CONFIG, utils::*};
+
+fn policy() -> &'static ChannelPolicy {
+    &CONFIG.get().unwrap().verification.channels
+}
+
+pub fn required_channels() -> Vec<RequiredChannel> {
+    if policy().required.is_empty() {
+        vec![RequiredChannel {
+            id: CONFIG.get().unwrap().master_channel,
+            title: None,
+            url: None,
+        }]
+    } else {
+        policy().required.clone()
+    }
+}
+
+fn channel_name(channel: &RequiredChannel) -> String {
+    let title = htmlescape::encode_minimal(channel.title.as_deref().unwrap_or("主频道"));
+    match &channel.url {
+        Some(url) => format!("<a href=\"{}\">{}</a>", url, title),
+        None => title,
+    }
+}
+
+pub async fn missing_channels(bot: &Bot, user_id: UserId) -> Result<Vec<RequiredChannel>> {
+    let mut missing = Vec::new();
+    for channel in required_channels() {
+        if !bot.get_chat_member(channel.id, user_id).await?.is_present() {
+            missing.push(channel);
+        }
+    }
+    Ok(missing)
+}
+
+pub fn satisfied(missing: &[RequiredChannel]) -> bool {
+    match policy().mode {
+        ChannelMode::All => missing.is_empty(),
+        ChannelMode::Any => missing.len() < required_channels().len(),
+    }
+}
+
+pub async fn in_required_channels(bot: &Bot, user_id: UserId) -> Result<bool> {
+    Ok(satisfied(&missing_channels(bot, user_id).await?))
+}
+
+/// Mutes the user and lists the channels to join, the verification continues once they are joined.
+pub async fn wait_for_channels(bot: Bot, user: User, chat_id: ChatId) -> Result<()> {
+    bot.restrict_chat_member(chat_id, user.id, ChatPermissions::empty())
+        .await?;
+
+    let deadline = Utc::now() + Duration::minutes(policy().grace_minutes);
+    let mut data = QuestionData {
+        private: policy().notice_in_private,
+        ..QuestionData::new(
+            user.clone(),
+            chat_id,
+            <ChannelHandler as Handler>::NAME,
+            Default::default(),
+            deadline,
+        )
+    };
+
+    let res = super::send_question_message(&bot, &data, None).await;
+    let msg: Message = match res {
+        Ok(msg) => msg,
+        // the user may have never started the bot
+        Err(_) if data.private => {
+            data.private = false;
+            super::send_question_message(&bot, &data, None).await?
+        }
+        Err(err) => return Err(err),
+    };
+    super::add_wating_user((msg.chat.id, msg.id), data).await?;
+
+    Ok(())
+}
+
+/// Waits for users to join the required channels during the grace period.
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelHandler;
+
+impl Handler for ChannelHandler {
+    const NAME: &'static str = "Channel";
+
+    type Id = ();
+    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, _: ()) -> Result<()> {
+        wait_for_channels(bot, user, chat.id).await
+    }
+
+    fn keyboard_patch(&self, keyboard: InlineKeyboardMarkup) -> InlineKeyboardMarkup {
+        let mut keyboard = keyboard;
+        for channel in required_channels() {
+            if let Some(url) = channel.url.as_ref().and_then(|url| Url::parse(url).ok()) {
+                let title = channel.title.clone().unwrap_or_else(|| "主频道".to_string());
+                keyboard = keyboard.append_row(vec![InlineKeyboardButton::url(format!("加入 {}", title), url)]);
+            }
+        }
+        keyboard
+            .append_row(vec![InlineKeyboardButton::callback("我已加入✅", "joined")])
+            .append_row(vec![
+                InlineKeyboardButton::callback("手动踢出🚫", "admin-ban"),
+                InlineKeyboardButton::callback("手动通过✅", "admin-allow"),
+            ])
+    }
+
+    fn message(&self, data: &QuestionData) -> Option<String> {
+        let which = match policy().mode {
+            ChannelMode::All => "以下全部频道",
+            ChannelMode::Any => "以下任一频道",
+        };
+        let channels = required_channels()
+            .iter()
+            .map(|channel| format!("• {}", channel_name(channel)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(format!(
+            "{}，请在 {} 分钟内加入{}，然后点击「我已加入」：\n{}",
+            metion_user(&data.user),
+            data.left_minutes(),
+            which,
+            channels
+        ))
+    }
+
+    async fn handle_correct(&mut self, _bot: Bot, _key: QuestionKey) -> Result<Option<String>> {
+        res!()
+    }
+
+    async fn handle_wrong(&mut self, _bot: Bot, _key: QuestionKey) -> Result<Option<String>> {
+        res!()
+    }
+
+    async fn handle_other(&mut self, bot: Bot, word: &str, key: QuestionKey) -> Result<Option<String>> {
+        match word {
+            "joined" => {
+                let user_id = if let Some(data) = get_data_by_msg(&key).await {
+                    data.user.id
+                } else {
+                    return res!();
+                };
+                let missing = missing_channels(&bot, user_id).await?;
+                if !satisfied(&missing) {
+                    let names = missing
+                        .iter()
+                        .map(|channel| channel.title.clone().unwrap_or_else(|| "主频道".to_string()))
+                        .collect::<Vec<_>>()
+                        .join("、");
+                    return res!(("仍未加入：{}", names));
+                }
+                if let Some((key, data)) = user_finish(key).await {
+                    super::TO_DELETE_MESSAGE.push(key);
+                    // still muted, the verification unmutes only when it admits the user without a question
+                    super::join_handler::verify(bot, data.user, data.chat_id, true).await?;
+                }
+                res!("已确认加入频道")
+            }
+            "admin-allow" => {
+                if let Some((key, data)) = user_finish(key).await {
+                    super::TO_DELETE_MESSAGE.push(key);
+                    bot.restrict_chat_member(data.chat_id, data.user.id, ChatPermissions::all())
+                        .await?;
+                    admin_log(bot, format!("管理员手动允许 {} 加入", metion_user(&data.user))).await?;
+                }
+                res!()
+            }
+            "admin-ban" => {
+                if let Some((key, data)) = user_finish(key).await {
+                    super::TO_DELETE_MESSAGE.push(key);
+                    bot.ban_chat_member(data.chat_id, data.user.id).await?;
+                    admin_log(bot, format!("管理员手动踢出 {}", metion_user(&data.user))).await?;
+                }
+                res!()
+            }
+            _ => res!(("未知命令：{}", word)),
+        }
+    }
+
+    async fn handle_timeout(&mut self, bot: Bot, (key, data): (QuestionKey, QuestionData)) -> Result<()> {
+        super::TO_DELETE_MESSAGE.push(key);
+        let mut req = bot.ban_chat_member(data.chat_id, data.user.id);
+        req.until_date =
+            Some(Utc::now() + Duration::minutes(CONFIG.get().unwrap().verification.join.master_channel_kick_minutes));
+        req.await?;
+        admin_log(
+            bot,
+            format!("用户 {} 未在宽限期内加入频道，已踢出。", metion_user(&data.user)),
+        )
+        .await
+    }
+}
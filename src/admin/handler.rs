@@ -1,11 +1,24 @@
-use anyhow::Result;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Result, anyhow};
+use futures::future::BoxFuture;
 use teloxide::types::{Chat, InlineKeyboardMarkup, MessageId, User};
 
+use super::{QuestionData, QuestionKey};
 use crate::Bot;
 
 pub trait Handler {
+    /// Saved with every question, so it must stay the same across versions.
+    const NAME: &'static str;
+
     type Id = MessageId;
 
+    /// The user can not post in the group while answering, text answers have to be sent by DM.
+    const MUTED: bool = false;
+
     fn send_question(
         &mut self,
         bot: Bot,
@@ -18,24 +31,36 @@ pub trait Handler {
         keyboard
     }
 
+    /// Replaces the question text, for handlers that do not ask a question.
+    fn message(&self, _data: &QuestionData) -> Option<String> {
+        None
+    }
+
     fn handle_correct(
         &mut self,
         bot: Bot,
-        msg_id: MessageId,
+        key: QuestionKey,
     ) -> impl std::future::Future<Output = Result<Option<String>>> + Send;
 
     fn handle_wrong(
         &mut self,
         bot: Bot,
-        msg_id: MessageId,
+        key: QuestionKey,
     ) -> impl std::future::Future<Output = Result<Option<String>>> + Send;
 
     fn handle_other(
         &mut self,
         bot: Bot,
         word: &str,
-        msg_id: MessageId,
+        key: QuestionKey,
     ) -> impl std::future::Future<Output = Result<Option<String>>> + Send;
+
+    /// Called once the question is finished without an answer before its deadline.
+    fn handle_timeout(
+        &mut self,
+        bot: Bot,
+        data: (QuestionKey, QuestionData),
+    ) -> impl std::future::Future<Output = Result<()>> + Send;
 }
 
 macro_rules! res {
@@ -54,69 +79,78 @@ macro_rules! res {
 }
 pub(crate) use res;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum HandlerKind {
-    Join,
-    Link,
-    Test,
+/// Object safe side of [`Handler`], used to dispatch by the handler name saved in the question.
+pub trait QuestionHandler: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn muted(&self) -> bool;
+
+    fn keyboard_patch(&self, keyboard: InlineKeyboardMarkup) -> InlineKeyboardMarkup;
+
+    fn message(&self, data: &QuestionData) -> Option<String>;
+
+    fn handle_correct(&self, bot: Bot, key: QuestionKey) -> BoxFuture<'static, Result<Option<String>>>;
+
+    fn handle_wrong(&self, bot: Bot, key: QuestionKey) -> BoxFuture<'static, Result<Option<String>>>;
+
+    fn handle_other<'a>(&self, bot: Bot, word: &'a str, key: QuestionKey) -> BoxFuture<'a, Result<Option<String>>>;
+
+    fn handle_timeout(&self, bot: Bot, data: (QuestionKey, QuestionData)) -> BoxFuture<'static, Result<()>>;
 }
 
-impl Handler for HandlerKind {
-    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, message_id: MessageId) -> Result<()> {
-        match self {
-            HandlerKind::Join => {
-                super::join_handler::JoinHandler
-                    .send_question(bot, user, chat, ())
-                    .await
-            }
-            HandlerKind::Link => {
-                super::link_handler::LinkHandler
-                    .send_question(bot, user, chat, message_id)
-                    .await
-            }
-            HandlerKind::Test => {
-                unimplemented!()
-            }
-        }
+impl<T: Handler + Clone + Send + Sync + 'static> QuestionHandler for T {
+    fn name(&self) -> &'static str {
+        T::NAME
+    }
+
+    fn muted(&self) -> bool {
+        T::MUTED
     }
 
     fn keyboard_patch(&self, keyboard: InlineKeyboardMarkup) -> InlineKeyboardMarkup {
-        match self {
-            HandlerKind::Join => super::join_handler::JoinHandler.keyboard_patch(keyboard),
-            HandlerKind::Link => super::link_handler::LinkHandler.keyboard_patch(keyboard),
-            HandlerKind::Test => {
-                unimplemented!()
-            }
-        }
+        Handler::keyboard_patch(self, keyboard)
+    }
+
+    fn message(&self, data: &QuestionData) -> Option<String> {
+        Handler::message(self, data)
+    }
+
+    fn handle_correct(&self, bot: Bot, key: QuestionKey) -> BoxFuture<'static, Result<Option<String>>> {
+        let mut handler = self.clone();
+        Box::pin(async move { Handler::handle_correct(&mut handler, bot, key).await })
     }
 
-    async fn handle_correct(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
-        match self {
-            HandlerKind::Join => super::join_handler::JoinHandler.handle_correct(bot, msg_id).await,
-            HandlerKind::Link => super::link_handler::LinkHandler.handle_correct(bot, msg_id).await,
-            HandlerKind::Test => {
-                unimplemented!()
-            }
-        }
+    fn handle_wrong(&self, bot: Bot, key: QuestionKey) -> BoxFuture<'static, Result<Option<String>>> {
+        let mut handler = self.clone();
+        Box::pin(async move { Handler::handle_wrong(&mut handler, bot, key).await })
     }
 
-    async fn handle_wrong(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
-        match self {
-            HandlerKind::Join => super::join_handler::JoinHandler.handle_wrong(bot, msg_id).await,
-            HandlerKind::Link => super::link_handler::LinkHandler.handle_wrong(bot, msg_id).await,
-            HandlerKind::Test => {
-                unimplemented!()
-            }
-        }
+    fn handle_other<'a>(&self, bot: Bot, word: &'a str, key: QuestionKey) -> BoxFuture<'a, Result<Option<String>>> {
+        let mut handler = self.clone();
+        Box::pin(async move { Handler::handle_other(&mut handler, bot, word, key).await })
     }
 
-    async fn handle_other(&mut self, bot: Bot, word: &str, msg_id: MessageId) -> Result<Option<String>> {
-        match self {
-            HandlerKind::Join => super::join_handler::JoinHandler.handle_other(bot, word, msg_id).await,
-            HandlerKind::Link => super::link_handler::LinkHandler.handle_other(bot, word, msg_id).await,
-            HandlerKind::Test => {
-                unimplemented!()
-            }
-        }
+    fn handle_timeout(&self, bot: Bot, data: (QuestionKey, QuestionData)) -> BoxFuture<'static, Result<()>> {
+        let mut handler = self.clone();
+        Box::pin(async move { Handler::handle_timeout(&mut handler, bot, data).await })
     }
 }
+
+static REGISTRY: RwLock<BTreeMap<&'static str, Arc<dyn QuestionHandler>>> = RwLock::new(BTreeMap::new());
+
+/// Makes `handler` reachable from the questions it sends, by its [`Handler::NAME`].
+pub fn register<T: Handler + Clone + Send + Sync + 'static>(handler: T) {
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.insert(T::NAME, Arc::new(handler)).is_some() {
+        log::warn!("Handler {} registered twice", T::NAME);
+    }
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn QuestionHandler>> {
+    REGISTRY.read().unwrap().get(name).cloned()
+}
+
+/// Looks up the handler of a question, failing for names no longer registered.
+pub fn get_or_err(name: &str) -> Result<Arc<dyn QuestionHandler>> {
+    get(name).ok_or_else(|| anyhow!("未注册的处理器：{}", name))
+}
@@ -1,179 +1,306 @@
 use anyhow::Result;
-use chrono::{DateTime, Duration, Utc};
+use chrono::{Duration, Utc};
 use log::debug;
-use rand::{Rng, rng};
-use reqwest::Url;
+use rand::{Rng, distr::Alphanumeric, rng};
+use redis::AsyncCommands;
 use teloxide::{
     payloads::{EditMessageTextSetters, SendMessageSetters},
     requests::Requester,
-    types::{Chat, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, ParseMode, User, UserId},
+    types::{Chat, ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, ParseMode, User, UserId},
 };
 
-use super::{QuestionData, auth_database, get_data_by_msg, handler::*, user_finish};
-use crate::{Bot, CONFIG, question, utils::*};
-
-async fn check_cas(bot: Bot, chat_id: ChatId, user_id: UserId, msg_id: i32) -> Result<()> {
-    let ok = reqwest::get(Url::parse_with_params(
-        "https://api.cas.chat/check",
-        &[("user_id", user_id.to_string())],
-    )?)
-    .await?
-    .json::<super::CasResult>()
-    .await?
-    .ok;
+use super::{
+    QuestionData, QuestionKey,
+    auth_database::{self, AuthInfo, AuthMethod, Scope},
+    channel_handler, get_data_by_msg,
+    handler::*,
+    penalty,
+    policy::JoinPolicy,
+    update_wating_user, user_finish,
+};
+use crate::{Bot, CONFIG, heuristics, question, utils::*};
 
-    if !ok {
+async fn check_reputation(bot: Bot, user_id: UserId, key: QuestionKey, bypass_cache: bool) -> Result<()> {
+    let verdict = super::reputation::check(user_id, bypass_cache).await;
+    super::reputation::report_errors(bot.clone(), &verdict).await?;
+    if !verdict.flagged() {
         return Ok(());
     }
 
-    let mut user = if let Some(data) = super::get_data_by_msg(&msg_id).await {
+    let key = super::moved_question(key).await.unwrap_or(key);
+    let mut user = if let Some(data) = super::get_data_by_msg(&key).await {
         data
     } else {
         return Ok(());
     };
-    let keyboard =
-        InlineKeyboardMarkup::default().append_row(vec![InlineKeyboardButton::callback("确认踢出", "admin-ban")]);
+    // already warned by an earlier check
+    if user.cas.is_some() {
+        return Ok(());
+    }
+    // the warning goes to the group, under the prompt once the question moved to the private chat
+    let group_msg = if user.private { user.prompt } else { Some(key.1) };
+    let group_msg = if let Some(group_msg) = group_msg {
+        group_msg
+    } else {
+        return Ok(());
+    };
+    let keyboard = InlineKeyboardMarkup::default().append_row(vec![InlineKeyboardButton::callback(
+        "确认踢出",
+        user.callback_data("admin-ban", Some(group_msg)),
+    )]);
     let res = bot
         .send_message(
-            chat_id,
-            format!(
-                "⚠️管理员注意，<a href=\"https://cas.chat/query?u={}\">该用户已被 CAS 封禁</a>",
-                user.user.id
-            ),
+            user.chat_id,
+            format!("⚠️管理员注意，该用户已被标记：{}", verdict.sources()),
         )
-        .reply_to_message_id(MessageId(msg_id))
+        .reply_to_message_id(group_msg)
         .parse_mode(ParseMode::Html)
         .reply_markup(keyboard)
         .disable_web_page_preview()
         .await?;
 
     user.cas = Some(res.id);
+    update_wating_user(key, &user).await;
 
     Ok(())
 }
 
-pub async fn in_master_channel(bot: &Bot, user_id: UserId) -> Result<bool> {
-    let member = bot
-        .get_chat_member(CONFIG.get().unwrap().master_channel, user_id)
-        .await?;
-    Ok(member.is_present())
-}
-
-#[derive(Debug, Clone, Copy)]
-pub struct JoinHandler;
+/// Re-checks the reputation of users still answering and of users admitted recently, catching late listings. A
+/// failed check is logged and the scan goes on with the next user.
+pub async fn rescan_reputation(bot: Bot) -> Result<()> {
+    let pending = super::pending_questions(<JoinHandler as Handler>::NAME).await;
+    for (key, data) in pending {
+        if data.cas.is_some() {
+            continue;
+        }
+        if let Err(err) = check_reputation(bot.clone(), data.user.id, key, true).await {
+            log::error!("Failed to rescan the reputation of {}: {}", data.user.id, err);
+        }
+    }
 
-impl Handler for JoinHandler {
-    type Id = ();
-    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, _: ()) -> Result<()> {
-        if user.is_bot {
-            return Ok(());
+    let hours = CONFIG.get().unwrap().reputation.rescan_admitted_hours;
+    let admitted: Vec<u64> = {
+        let mut con = crate::get_connection().await;
+        let since = (Utc::now() - Duration::hours(hours)).timestamp();
+        () = con.zrembyscore(RECENT_ADMITTED_KEY, "-inf", since).await?;
+        con.zrange(RECENT_ADMITTED_KEY, 0, -1).await?
+    };
+    for user_id in admitted {
+        let user_id = UserId(user_id);
+        if let Err(err) = rescan_admitted(bot.clone(), user_id).await {
+            log::error!("Failed to rescan the reputation of {}: {}", user_id, err);
         }
+    }
+    Ok(())
+}
 
-        if auth_database::is_authed(user.id.0).await? {
-            bot.send_message(chat.id, format!("{}，欢迎！", metion_user(&user)))
-                .parse_mode(ParseMode::Html)
-                .await?;
+async fn rescan_admitted(bot: Bot, user_id: UserId) -> Result<()> {
+    let verdict = super::reputation::check(user_id, true).await;
+    super::reputation::report_errors(bot.clone(), &verdict).await?;
+    if !verdict.flagged() {
+        return Ok(());
+    }
+    bot.send_message(
+        CONFIG.get().unwrap().admin_log,
+        format!(
+            "⚠️管理员注意，近期通过验证的用户 <a href=\"tg://user?id={}\">{}</a> 已被标记：{}",
+            user_id,
+            user_id,
+            verdict.sources()
+        ),
+    )
+    .parse_mode(ParseMode::Html)
+    .disable_web_page_preview()
+    .await?;
+    let mut con = crate::get_connection().await;
+    () = con.zrem(RECENT_ADMITTED_KEY, user_id.0).await?;
+    Ok(())
+}
 
-            return Ok(());
-        }
+const RECENT_ADMITTED_KEY: &str = "shit_bot_recent_admitted";
 
-        let in_channel = in_master_channel(&bot, user.id).await;
-        debug!("user {} in master channel: {:?}", user.full_name(), in_channel);
-        match in_channel {
-            Ok(true) => {}
-            Ok(false) => {
-                let mut req = bot.ban_chat_member(chat.id, user.id);
-                req.until_date = Some(Utc::now() + Duration::minutes(1));
-                if let Err(err) = req.await {
-                    bot.send_message(
-                        chat.id,
-                        format!("用户 {} 未加入主频道，但是踢出用户失败：{}", metion_user(&user), err),
-                    )
-                    .parse_mode(ParseMode::Html)
-                    .await?;
-                } else {
-                    let _ = admin_log(bot, format!("用户 {} 未加入主频道，已提出。", metion_user(&user))).await;
-                    return Ok(());
-                }
+fn policy() -> &'static JoinPolicy {
+    &CONFIG.get().unwrap().verification.join
+}
+
+/// Drops the questions of a user who left the group before finishing them, banning join-leave accounts.
+pub async fn handle_leave(bot: Bot, chat_id: ChatId, user: User) -> Result<()> {
+    let mut joining = None;
+    for key in super::questions_of_user(chat_id, user.id).await {
+        if let Some((key, data)) = user_finish(key).await {
+            super::TO_DELETE_MESSAGE.push(key);
+            if let Some(prompt) = data.prompt {
+                super::TO_DELETE_MESSAGE.push((data.chat_id, prompt));
+            }
+            if let Some(cas) = data.cas {
+                super::TO_DELETE_MESSAGE.push((data.chat_id, cas));
             }
-            Err(err) => {
-                bot.send_message(chat.id, format!("检查 {} 频道存在失败：{}", metion_user(&user), err))
-                    .parse_mode(ParseMode::Html)
-                    .await
-                    .ok();
+            // the unverified link message goes too, unless it is already hidden
+            if let Some(message_id) = data.message_id.filter(|_| data.hidden.is_none()) {
+                super::TO_DELETE_MESSAGE.push((data.chat_id, message_id));
+            }
+            if data.handler == <JoinHandler as Handler>::NAME
+                || data.handler == <channel_handler::ChannelHandler as Handler>::NAME
+            {
+                joining = Some(data);
             }
         }
+    }
+    while let Some((chat, msg)) = super::TO_DELETE_MESSAGE.pop() {
+        bot.delete_message(chat, msg).await.ok();
+    }
+    let data = if let Some(data) = joining {
+        data
+    } else {
+        return Ok(());
+    };
+
+    if policy().ban_on_leave {
+        let ban_minutes = penalty::next_ban_minutes(user.id).await?;
+        let mut req = bot.ban_chat_member(chat_id, user.id);
+        req.until_date = penalty::ban_until(ban_minutes);
+        req.await?;
+        let penalty = penalty::describe_ban(ban_minutes);
+        penalty::record(&data, format!("中途退群，{}", penalty)).await?;
+        admin_log(
+            bot,
+            format!("用户 {} 验证中途退群，已{}。", metion_user(&user), penalty),
+        )
+        .await
+    } else {
+        admin_log(bot, format!("用户 {} 验证中途退群。", metion_user(&user))).await
+    }
+}
 
-        if user.is_premium {
-            bot.send_message(chat.id, format!("Premium 用户 {}，欢迎！", metion_user(&user)))
+/// Verifies a user that joined `chat_id`, from the channel check to the question. `muted` users, e.g. held during a
+/// lockdown or waiting for the channels, are unmuted when admitted without a question.
+pub async fn verify(bot: Bot, user: User, chat_id: ChatId, muted: bool) -> Result<()> {
+    if user.is_bot {
+        return Ok(());
+    }
+
+    if auth_database::is_authed(user.id, chat_id).await? {
+        if muted {
+            unmute(&bot, chat_id, user.id).await?;
+        }
+        bot.send_message(chat_id, format!("{}，欢迎！", metion_user(&user)))
+            .parse_mode(ParseMode::Html)
+            .await?;
+
+        return Ok(());
+    }
+
+    if super::lockdown::intercept_join(&bot, &user, chat_id).await? {
+        return Ok(());
+    }
+
+    let in_channel = channel_handler::in_required_channels(&bot, user.id).await;
+    debug!("user {} in required channels: {:?}", user.full_name(), in_channel);
+    match in_channel {
+        Ok(true) => {}
+        Ok(false) if CONFIG.get().unwrap().verification.channels.grace_minutes > 0 => {
+            return channel_handler::wait_for_channels(bot, user, chat_id).await;
+        }
+        Ok(false) => {
+            let mut req = bot.ban_chat_member(chat_id, user.id);
+            req.until_date = Some(Utc::now() + Duration::minutes(policy().master_channel_kick_minutes));
+            if let Err(err) = req.await {
+                bot.send_message(
+                    chat_id,
+                    format!("用户 {} 未加入指定频道，但是踢出用户失败：{}", metion_user(&user), err),
+                )
                 .parse_mode(ParseMode::Html)
                 .await?;
+            } else {
+                let _ = admin_log(bot, format!("用户 {} 未加入指定频道，已踢出。", metion_user(&user))).await;
+                return Ok(());
+            }
+        }
+        Err(err) => {
+            bot.send_message(chat_id, format!("检查 {} 频道存在失败：{}", metion_user(&user), err))
+                .parse_mode(ParseMode::Html)
+                .await
+                .ok();
+        }
+    }
 
-            return Ok(());
+    if user.is_premium && policy().premium_skip {
+        let info = AuthInfo::new(AuthMethod::Premium, Some(chat_id));
+        auth_database::add_authed(user.id, Scope::of(chat_id), info).await?;
+        if muted {
+            unmute(&bot, chat_id, user.id).await?;
         }
+        bot.send_message(chat_id, format!("Premium 用户 {}，欢迎！", metion_user(&user)))
+            .parse_mode(ParseMode::Html)
+            .await?;
 
-        let (title, options, correct_idx) = question::new_question();
+        return Ok(());
+    }
 
-        // mute user
-        let res = bot
-            .restrict_chat_member(chat.id, user.id, teloxide::types::ChatPermissions::empty())
-            .await;
-        if let Err(err) = res {
-            bot.send_message(chat.id, err.to_string()).await?;
-            return Err(err.into());
-        }
+    // mute user
+    let res = bot
+        .restrict_chat_member(chat_id, user.id, teloxide::types::ChatPermissions::empty())
+        .await;
+    if let Err(err) = res {
+        bot.send_message(chat_id, err.to_string()).await?;
+        return Err(err.into());
+    }
 
-        let data = super::QuestionData {
-            user: user.clone(),
-            chat_id: chat.id,
-            message_id: None,
-            title,
-            options,
-            correct: correct_idx,
-            tried_times: 0,
-            cas: None,
-            left_minutes: 5,
-            handler: super::handler::HandlerKind::Join,
-        };
+    let kind = question::random_kind(policy().text_question_ratio, policy().image_question_ratio);
+    let deadline = Utc::now() + Duration::minutes(policy().timeout_minutes);
+    let mut data = super::QuestionData {
+        token: policy()
+            .private_quiz
+            .then(|| rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect()),
+        ..super::QuestionData::new(user.clone(), chat_id, <JoinHandler as Handler>::NAME, kind, deadline)
+    };
+    data.renew_question();
 
-        let res = bot
-            .send_message(chat.id, data.message())
-            .parse_mode(ParseMode::Html)
-            .reply_markup(data.keyboard(true))
-            .await;
-
-        let msg: Message = match res {
-            Ok(msg) => msg,
-            Err(err) => {
-                bot.send_message(chat.id, format!("问题发送失败，自动允许加入\n{}", err))
-                    .await?;
-                let res = bot
-                    .restrict_chat_member(chat.id, user.id, teloxide::types::ChatPermissions::all())
-                    .await;
-                if let Err(err) = res {
-                    bot.send_message(
-                        chat.id,
-                        format!("⚠️管理员注意！解除禁言失败，请管理员手动解除\n{}", err),
-                    )
-                    .await?;
-                    return Err(err.into());
-                }
+    let res = super::send_question_message(&bot, &data, None).await;
+
+    let msg: Message = match res {
+        Ok(msg) => msg,
+        Err(err) => {
+            bot.send_message(chat_id, format!("问题发送失败，自动允许加入\n{}", err))
+                .await?;
+            let res = bot
+                .restrict_chat_member(chat_id, user.id, teloxide::types::ChatPermissions::all())
+                .await;
+            if let Err(err) = res {
+                bot.send_message(
+                    chat_id,
+                    format!("⚠️管理员注意！解除禁言失败，请管理员手动解除\n{}", err),
+                )
+                .await?;
                 return Err(err.into());
             }
-        };
+            return Err(err);
+        }
+    };
+
+    super::add_wating_user((msg.chat.id, msg.id), data).await?;
+    let bot2 = bot.clone();
+    tokio::spawn(check_reputation(bot2, user.id, (msg.chat.id, msg.id), false));
+
+    Ok(())
+}
 
-        super::add_wating_user(msg.id, data).await;
+async fn unmute(bot: &Bot, chat_id: ChatId, user_id: UserId) -> Result<()> {
+    bot.restrict_chat_member(chat_id, user_id, teloxide::types::ChatPermissions::all())
+        .await?;
+    Ok(())
+}
 
-        let bot2 = bot.clone();
-        let _handle = tokio::spawn(super::waiting_answer(bot.clone(), msg.id, |data| async move {
-            ban(bot2, data, Some(Utc::now() + Duration::minutes(10))).await.ok();
-        }));
-        // add_wating_handle(msg.id, handle.abort_handle()).await;
+#[derive(Debug, Clone, Copy)]
+pub struct JoinHandler;
 
-        let bot2 = bot.clone();
-        tokio::spawn(check_cas(bot2, chat.id, user.id, msg.id.0));
+impl Handler for JoinHandler {
+    const NAME: &'static str = "Join";
+    const MUTED: bool = true;
 
-        Ok(())
+    type Id = ();
+    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, _: ()) -> Result<()> {
+        verify(bot, user, chat.id, false).await
     }
 
     fn keyboard_patch(&self, keyboard: InlineKeyboardMarkup) -> InlineKeyboardMarkup {
@@ -183,52 +310,67 @@ impl Handler for JoinHandler {
         ])
     }
 
-    async fn handle_correct(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
-        if let Some(data) = user_finish(msg_id).await {
+    async fn handle_correct(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        if let Some(data) = user_finish(key).await {
+            let info = AuthInfo::new(AuthMethod::Quiz, Some(data.1.chat_id));
+            auth_database::add_authed(data.1.user.id, Scope::of(data.1.chat_id), info).await?;
             allow(bot, data, false).await?;
         }
         res!("回答正确，验证通过")
     }
 
-    async fn handle_wrong(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
-        let (cas, tried_times, rank) = {
-            if let Some(data) = get_data_by_msg(&msg_id.0).await {
-                (data.cas, data.tried_times, rank_user(&data.user))
+    async fn handle_wrong(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        let (cas, tried_times, user) = {
+            if let Some(data) = get_data_by_msg(&key).await {
+                (data.cas, data.tried_times, data.user.clone())
             } else {
                 return res!();
             }
         };
+        let score = if tried_times == 0 {
+            heuristics::assess(&bot, &user).await.score
+        } else {
+            0.0
+        };
         if cas.is_some() {
-            if let Some(data) = user_finish(msg_id).await {
-                ban(bot, data, None).await?;
+            if let Some(data) = user_finish(key).await {
+                ban(bot, data, 0).await?;
             }
             res!("验证失败")
-        } else if tried_times >= 2 {
-            if let Some(data) = user_finish(msg_id).await {
-                ban(bot, data, Some(Utc::now() + Duration::minutes(10))).await?;
+        } else if tried_times >= policy().max_wrong_attempts {
+            let ban_minutes = penalty::next_ban_minutes(user.id).await?;
+            if let Some(data) = user_finish(key).await {
+                ban(bot, data, ban_minutes).await?;
+            }
+            if ban_minutes > 0 {
+                res!(("验证失败，失败次数过多，请 {} 分钟后重新加入", ban_minutes))
+            } else {
+                res!("验证失败，失败次数过多，已被永久封禁")
             }
-            res!("验证失败，失败次数过多，请十分钟后重新加入")
-        } else if tried_times == 0 && rng().random_bool(rank) {
-            if let Some(data) = user_finish(msg_id).await {
+        } else if tried_times == 0 && rng().random_bool(score) {
+            if let Some(data) = user_finish(key).await {
                 allow(bot, data, true).await?;
             }
             res!("尽管你回答错误了，但我们还是允许你加入。")
         } else {
-            if let Some(mut data) = get_data_by_msg(&msg_id.0).await {
+            if let Some(mut data) = get_data_by_msg(&key).await {
                 data.tried_times += 1;
+                update_wating_user(key, &data).await;
             }
             res!("验证失败")
         }
     }
 
-    async fn handle_other(&mut self, bot: Bot, word: &str, msg_id: MessageId) -> Result<Option<String>> {
+    async fn handle_other(&mut self, bot: Bot, word: &str, key: QuestionKey) -> Result<Option<String>> {
         if word == "admin-ban" {
-            if let Some(data) = user_finish(msg_id).await {
-                ban(bot, data, None).await?;
+            if let Some(data) = user_finish(key).await {
+                ban(bot, data, 0).await?;
             }
             res!()
         } else if word == "admin-allow" {
-            if let Some(data) = user_finish(msg_id).await {
+            if let Some(data) = user_finish(key).await {
+                let info = AuthInfo::new(AuthMethod::AdminAllow, Some(data.1.chat_id));
+                auth_database::add_authed(data.1.user.id, Scope::of(data.1.chat_id), info).await?;
                 allow(bot, data, false).await?;
             }
             res!()
@@ -236,9 +378,14 @@ impl Handler for JoinHandler {
             res!(("未知命令：{}", word))
         }
     }
+
+    async fn handle_timeout(&mut self, bot: Bot, data: (QuestionKey, QuestionData)) -> Result<()> {
+        let ban_minutes = penalty::next_ban_minutes(data.1.user.id).await?;
+        ban(bot, data, ban_minutes).await
+    }
 }
 
-async fn allow(bot: Bot, (msg_id, data): (i32, QuestionData), remain_cas: bool) -> Result<()> {
+async fn allow(bot: Bot, (key, data): (QuestionKey, QuestionData), remain_warning: bool) -> Result<()> {
     let res = bot
         .restrict_chat_member(data.chat_id, data.user.id, teloxide::types::ChatPermissions::all())
         .await;
@@ -250,13 +397,21 @@ async fn allow(bot: Bot, (msg_id, data): (i32, QuestionData), remain_cas: bool)
         .await?;
         return Err(err.into());
     }
-    super::TO_DELETE_MESSAGE.push((data.chat_id, MessageId(msg_id)));
+    super::TO_DELETE_MESSAGE.push(key);
+    if let Some(prompt) = data.prompt {
+        super::TO_DELETE_MESSAGE.push((data.chat_id, prompt));
+    }
+    {
+        let mut con = crate::get_connection().await;
+        () = con
+            .zadd(RECENT_ADMITTED_KEY, data.user.id.0, Utc::now().timestamp())
+            .await?;
+    }
 
     if let Some(cas) = data.cas {
-        if remain_cas {
+        if remain_warning {
             let text = format!(
-                "⚠️管理员注意，<a href=\"https://cas.chat/query?u={}\">CAS 封禁用户</a> {} 已通过验证加入群组",
-                data.user.id,
+                "⚠️管理员注意，被标记的用户 {} 已通过验证加入群组",
                 metion_user(&data.user)
             );
             bot.edit_message_text(data.chat_id, cas, text)
@@ -277,21 +432,26 @@ async fn allow(bot: Bot, (msg_id, data): (i32, QuestionData), remain_cas: bool)
     Ok(())
 }
 
-pub async fn ban(bot: Bot, (msg_id, data): (i32, QuestionData), until_date: Option<DateTime<Utc>>) -> Result<()> {
+/// Bans the user for `ban_minutes`, 0 for ever, and records the failure.
+pub async fn ban(bot: Bot, (key, data): (QuestionKey, QuestionData), ban_minutes: i64) -> Result<()> {
     let mut req = bot.ban_chat_member(data.chat_id, data.user.id);
-    req.until_date = until_date;
+    req.until_date = penalty::ban_until(ban_minutes);
     let res = req.await;
     if let Err(err) = res {
         bot.send_message(data.chat_id, err.to_string()).await?;
         return Err(err.into());
     }
-    super::TO_DELETE_MESSAGE.push((data.chat_id, MessageId(msg_id)));
+    penalty::record(&data, penalty::describe_ban(ban_minutes)).await?;
+    super::TO_DELETE_MESSAGE.push(key);
+    if let Some(prompt) = data.prompt {
+        super::TO_DELETE_MESSAGE.push((data.chat_id, prompt));
+    }
     // bot.delete_message(data.chat_id, data.message_id).await?;
     if let Some(cas) = data.cas {
         bot.delete_message(data.chat_id, cas).await?;
     }
 
-    let message = if is_spam_name(&data.user.full_name()) {
+    let message = if heuristics::assess(&bot, &data.user).await.is_spam() {
         "<filtered> 验证失败！".to_string()
     } else {
         format!("{} 验证失败，被扔进化粪池里了！", metion_user(&data.user))
@@ -300,3 +460,51 @@ pub async fn ban(bot: Bot, (msg_id, data): (i32, QuestionData), until_date: Opti
 
     Ok(())
 }
+
+/// Moves a question posted with a deep link into the private chat, once the user opened it with `/start`.
+pub async fn start_private_verify(bot: Bot, message: Message, token: &str) -> Result<()> {
+    let user = if let Some(user) = message.from.as_ref() {
+        user
+    } else {
+        return Ok(());
+    };
+    let key = if let Some(key) = super::find_by_token(token, user.id).await {
+        key
+    } else {
+        bot.send_message(message.chat.id, "验证链接已失效").await?;
+        return Ok(());
+    };
+    let (prompt, mut data) = if let Some(data) = user_finish(key).await {
+        data
+    } else {
+        return Ok(());
+    };
+
+    data.private = true;
+    data.token = None;
+    data.prompt = Some(prompt.1);
+    let res = super::send_question_message(&bot, &data, None).await;
+    let msg: Message = match res {
+        Ok(msg) => msg,
+        Err(err) => {
+            // keep the deep link usable
+            data.private = false;
+            data.token = Some(token.to_string());
+            data.prompt = None;
+            super::add_wating_user(prompt, data).await?;
+            return Err(err);
+        }
+    };
+    let text = format!("{} 正在私聊机器人完成验证", metion_user(&data.user));
+    let res = bot
+        .edit_message_text(prompt.0, prompt.1, text)
+        .parse_mode(ParseMode::Html)
+        .reply_markup(data.prompt_keyboard())
+        .await;
+    if let Err(err) = res {
+        log::error!("Failed to edit the prompt of {}: {}", user.id, err);
+    }
+    super::add_wating_user((msg.chat.id, msg.id), data).await?;
+
+    Ok(())
+}
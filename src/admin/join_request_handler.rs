@@ -0,0 +1,131 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{Chat, Message, ParseMode, User},
+};
+
+use super::{
+    QuestionData, QuestionKey,
+    auth_database::{self, AuthInfo, AuthMethod, Scope},
+    channel_handler::in_required_channels,
+    get_data_by_msg,
+    handler::{Handler, res},
+    policy::JoinRequestPolicy,
+    update_wating_user, user_finish,
+};
+use crate::{Bot, CONFIG, question, utils::*};
+
+fn policy() -> &'static JoinRequestPolicy {
+    &CONFIG.get().unwrap().verification.join_request
+}
+
+/// Answers join requests with a quiz in the private chat, approving or declining the request by the result.
+#[derive(Debug, Clone, Copy)]
+pub struct JoinRequestHandler;
+
+impl Handler for JoinRequestHandler {
+    const NAME: &'static str = "JoinRequest";
+
+    type Id = ();
+    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, _: ()) -> Result<()> {
+        if auth_database::is_authed(user.id, chat.id).await? || (user.is_premium && policy().premium_skip) {
+            bot.approve_chat_join_request(chat.id, user.id).await?;
+            return Ok(());
+        }
+
+        if let Ok(false) = in_required_channels(&bot, user.id).await {
+            bot.decline_chat_join_request(chat.id, user.id).await?;
+            bot.send_message(user.id, "请先加入指定频道后再申请加入群组").await.ok();
+            admin_log(bot, format!("用户 {} 未加入指定频道，已拒绝申请。", metion_user(&user))).await?;
+            return Ok(());
+        }
+
+        let kind = question::random_kind(policy().text_question_ratio, policy().image_question_ratio);
+        let deadline = Utc::now() + Duration::minutes(policy().timeout_minutes);
+        let mut data = QuestionData {
+            private: true,
+            ..QuestionData::new(user.clone(), chat.id, Self::NAME, kind, deadline)
+        };
+        data.renew_question();
+
+        let title = htmlescape::encode_minimal(chat.title().unwrap_or("群组"));
+        bot.send_message(
+            user.id,
+            format!("你申请加入「{}」，请回答下面的问题以通过申请。", title),
+        )
+        .parse_mode(ParseMode::Html)
+        .await?;
+        let res = super::send_question_message(&bot, &data, None).await;
+
+        let msg: Message = match res {
+            Ok(msg) => msg,
+            Err(err) => {
+                admin_log(
+                    bot.clone(),
+                    format!("向 {} 发送问题失败，请管理员手动处理申请\n{}", metion_user(&user), err),
+                )
+                .await?;
+                return Err(err);
+            }
+        };
+
+        super::add_wating_user((msg.chat.id, msg.id), data).await?;
+
+        Ok(())
+    }
+
+    async fn handle_correct(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        if let Some(data) = user_finish(key).await {
+            let info = AuthInfo::new(AuthMethod::Quiz, Some(data.1.chat_id));
+            auth_database::add_authed(data.1.user.id, Scope::of(data.1.chat_id), info).await?;
+            approve(bot, data).await?;
+        }
+        res!("回答正确，已通过申请")
+    }
+
+    async fn handle_wrong(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        let tried_times = {
+            if let Some(data) = get_data_by_msg(&key).await {
+                data.tried_times
+            } else {
+                return res!();
+            }
+        };
+        if tried_times >= policy().max_wrong_attempts {
+            if let Some(data) = user_finish(key).await {
+                decline(bot, data).await?;
+            }
+            res!("验证失败，失败次数过多，申请已被拒绝")
+        } else {
+            if let Some(mut data) = get_data_by_msg(&key).await {
+                data.tried_times += 1;
+                update_wating_user(key, &data).await;
+            }
+            res!("验证失败")
+        }
+    }
+
+    async fn handle_other(&mut self, _bot: Bot, word: &str, _key: QuestionKey) -> Result<Option<String>> {
+        res!(("未知命令：{}", word))
+    }
+
+    async fn handle_timeout(&mut self, bot: Bot, data: (QuestionKey, QuestionData)) -> Result<()> {
+        bot.send_message(data.1.user.id, "回答超时，申请已被拒绝").await.ok();
+        decline(bot, data).await
+    }
+}
+
+async fn approve(bot: Bot, (key, data): (QuestionKey, QuestionData)) -> Result<()> {
+    super::TO_DELETE_MESSAGE.push(key);
+    bot.approve_chat_join_request(data.chat_id, data.user.id).await?;
+    admin_log(bot, format!("{} 通过验证，已批准加入申请", metion_user(&data.user))).await
+}
+
+async fn decline(bot: Bot, (key, data): (QuestionKey, QuestionData)) -> Result<()> {
+    super::TO_DELETE_MESSAGE.push(key);
+    bot.decline_chat_join_request(data.chat_id, data.user.id).await?;
+    super::penalty::record(&data, "拒绝加入申请").await?;
+    admin_log(bot, format!("{} 验证失败，已拒绝加入申请", metion_user(&data.user))).await
+}
@@ -0,0 +1,255 @@
+//! Finds links in messages, including the ones without entities such as `t .me/xxx` or `t点me`, buttons, forwarded
+//! channel posts and stories.
+
+use std::{fmt, sync::LazyLock};
+
+use fancy_regex::Regex;
+use teloxide::types::{ChatId, InlineKeyboardButtonKind, Message, MessageEntityKind, MessageOrigin, UserId};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Link {
+    /// web address in lower case without the scheme, e.g. `example.com/path`
+    Url(String),
+    /// `@username` or `t.me/username`, in lower case without the `@`
+    Username(String),
+    /// mention of a user without username
+    User(UserId),
+    /// forwarded post or story of a chat without username
+    Chat(ChatId),
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Link::Url(url) => f.write_str(url),
+            Link::Username(username) => write!(f, "@{}", username),
+            Link::User(user_id) => write!(f, "用户 {}", user_id),
+            Link::Chat(chat_id) => write!(f, "群组 {}", chat_id),
+        }
+    }
+}
+
+// common in spam, a bare `word.word` is too often not a link
+const TLDS: &str = "com|net|org|info|io|me|cc|co|xyz|top|vip|club|site|online|shop|store|app|dev|link|ly|gg|tv|ru|cn|\
+                    hk|tw|pw|icu|fun|live|pro|biz|win|bet|cash|tk|ml|ga|cf|gq";
+
+static DOT_WORD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)[\[\(（【]\s*(?:\.|dot|点|點)\s*[\]\)）】]|\bdot\b|点|點").unwrap());
+static SEPARATOR_SPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*([./:])\s*").unwrap());
+static TELEGRAM_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?<![a-z0-9-])(?:t|telegram)\.(?:me|dog)/(?:joinchat/|\+|s/)?([a-z0-9_]{4,})").unwrap()
+});
+static WEB_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?<![a-z0-9-])(?:https?://)?((?:[a-z0-9-]+\.)+(?:{}))(?![a-z0-9-])(/[^\s]*)?",
+        TLDS
+    ))
+    .unwrap()
+});
+
+/// Folds full-width characters and drops zero-width ones, which never appear in ordinary text.
+fn fold(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| match c {
+            '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' => None,
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0),
+            c => Some(c),
+        })
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Folds the tricks used to hide links: full-width characters, other dots, `点`, zero-width characters and spaces
+/// around the separators.
+pub fn normalize(text: &str) -> String {
+    let dotted = fold(text).replace(['\u{3002}', '\u{FF61}', '\u{2024}', '\u{2E33}', '\u{00B7}'], ".");
+    let dotted = DOT_WORD.replace_all(&dotted, ".");
+    SEPARATOR_SPACE.replace_all(&dotted, "$1").into_owned()
+}
+
+/// `hidden` links only show up after [`normalize`], which also joins sentences like `me too. me as well`, so a web
+/// link among them needs a scheme or a path.
+fn find_links(text: &str, hidden: bool) -> Vec<Link> {
+    let mut links = Vec::new();
+    for captures in TELEGRAM_LINK.captures_iter(text).flatten() {
+        links.push(Link::Username(captures[1].to_string()));
+    }
+    for captures in WEB_LINK.captures_iter(text).flatten() {
+        let host = captures[1].trim_start_matches("www.");
+        if matches!(host, "t.me" | "telegram.me" | "telegram.dog") {
+            continue;
+        }
+        let path = captures
+            .get(2)
+            .map(|m| m.as_str().trim_end_matches('/'))
+            .unwrap_or_default();
+        if hidden && path.is_empty() && !captures[0].starts_with("http") {
+            continue;
+        }
+        links.push(Link::Url(format!("{}{}", host, path)));
+    }
+    links
+}
+
+fn links_in_text(text: &str) -> Vec<Link> {
+    let mut links = find_links(&fold(text), false);
+    links.extend(find_links(&normalize(text), true));
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Links of an entity or button URL, which is a link even with an unknown TLD.
+fn link_of_url(url: &str) -> Vec<Link> {
+    let links = links_in_text(url);
+    if !links.is_empty() {
+        return links;
+    }
+    let url = url.to_lowercase();
+    let url = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+    vec![Link::Url(url.trim_end_matches('/').to_string())]
+}
+
+fn link_of_chat(id: ChatId, username: Option<&str>) -> Link {
+    match username {
+        Some(username) => Link::Username(username.to_lowercase()),
+        None => Link::Chat(id),
+    }
+}
+
+/// Every link of the message, sorted and without duplicates.
+pub fn detect(msg: &Message) -> Vec<Link> {
+    let mut links = Vec::new();
+
+    let entities = [msg.parse_entities(), msg.parse_caption_entities()];
+    for entity in entities.into_iter().flatten().flatten() {
+        match entity.kind() {
+            MessageEntityKind::Url => links.extend(link_of_url(entity.text())),
+            MessageEntityKind::TextLink { url } => links.extend(link_of_url(url.as_str())),
+            MessageEntityKind::Mention => {
+                links.push(Link::Username(entity.text().trim_start_matches('@').to_lowercase()))
+            }
+            MessageEntityKind::TextMention { user } => links.push(Link::User(user.id)),
+            _ => {}
+        }
+    }
+
+    if let Some(text) = msg.text().or(msg.caption()) {
+        links.extend(links_in_text(text));
+    }
+
+    if let Some(markup) = msg.reply_markup() {
+        for button in markup.inline_keyboard.iter().flatten() {
+            match &button.kind {
+                InlineKeyboardButtonKind::Url(url) => links.extend(link_of_url(url.as_str())),
+                InlineKeyboardButtonKind::LoginUrl(login) => links.extend(link_of_url(login.url.as_str())),
+                InlineKeyboardButtonKind::WebApp(web_app) => links.extend(link_of_url(web_app.url.as_str())),
+                _ => {}
+            }
+            links.extend(links_in_text(&button.text));
+        }
+    }
+
+    match msg.forward_origin() {
+        Some(MessageOrigin::Channel { chat, .. }) | Some(MessageOrigin::Chat { sender_chat: chat, .. }) => {
+            links.push(link_of_chat(chat.id, chat.username()));
+        }
+        _ => {}
+    }
+
+    if let Some(story) = msg.story() {
+        links.push(link_of_chat(story.chat.id, story.chat.username()));
+    }
+
+    links.sort();
+    links.dedup();
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn message(extra: Value) -> Message {
+        let mut message = json!({
+            "message_id": 1,
+            "date": 0,
+            "chat": { "id": -1001, "type": "supergroup", "title": "test" },
+            "from": { "id": 42, "is_bot": false, "first_name": "spammer" },
+        });
+        message
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(message).unwrap()
+    }
+
+    fn detect_text(text: &str) -> Vec<Link> {
+        detect(&message(json!({ "text": text })))
+    }
+
+    #[test]
+    fn normalize_folds_obfuscation() {
+        assert_eq!(normalize("t .me/ spam_bot"), "t.me/spam_bot");
+        assert_eq!(normalize("ｔ．ｍｅ／ＳＰＡＭ"), "t.me/spam");
+        assert_eq!(normalize("t点me/spam"), "t.me/spam");
+        assert_eq!(normalize("t(dot)me/spam"), "t.me/spam");
+        assert_eq!(normalize("t\u{200B}。me/spam"), "t.me/spam");
+    }
+
+    #[test]
+    fn detect_obfuscated_links() {
+        assert_eq!(
+            detect_text("加我 t . me / spam_group"),
+            [Link::Username("spam_group".to_string())]
+        );
+        assert_eq!(
+            detect_text("ｔ．ｍｅ／ｓｐａｍ＿ｂｏｔ"),
+            [Link::Username("spam_bot".to_string())]
+        );
+        assert_eq!(
+            detect_text("看 example 点 com/promo"),
+            [Link::Url("example.com/promo".to_string())]
+        );
+        assert_eq!(
+            detect_text("https : // spam . xyz"),
+            [Link::Url("spam.xyz".to_string())]
+        );
+        assert_eq!(
+            detect_text("访问 www.example.com"),
+            [Link::Url("example.com".to_string())]
+        );
+        assert_eq!(detect_text("spam\u{200B}.xyz"), [Link::Url("spam.xyz".to_string())]);
+    }
+
+    #[test]
+    fn detect_ignores_ordinary_text() {
+        for text in [
+            "Me too. Me as well",
+            "1. App store",
+            "ok。me",
+            "好的。Me too",
+            "version 2. Live now",
+        ] {
+            assert_eq!(detect_text(text), [], "{}", text);
+        }
+    }
+
+    #[test]
+    fn detect_entities_and_buttons() {
+        let msg = message(json!({
+            "text": "@SpamBot here",
+            "entities": [{ "type": "mention", "offset": 0, "length": 8 }],
+            "reply_markup": { "inline_keyboard": [[{ "text": "join", "url": "https://Example.org/x/" }]] },
+        }));
+        assert_eq!(
+            detect(&msg),
+            [
+                Link::Url("example.org/x".to_string()),
+                Link::Username("spambot".to_string())
+            ]
+        );
+    }
+}
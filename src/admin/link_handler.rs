@@ -1,63 +1,160 @@
 use anyhow::Result;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
 use teloxide::{
     payloads::SendMessageSetters,
     requests::Requester,
     types::{Chat, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, ParseMode, User},
+    utils::render::RenderMessageTextHelper,
 };
 
 use super::{
-    QuestionData, auth_database, get_data_by_msg,
+    QuestionData, QuestionKey,
+    auth_database::{self, AuthInfo, AuthMethod, Scope},
+    get_data_by_msg,
     handler::{Handler, res},
-    user_finish,
+    link_detector::Link,
+    link_lists::{self, Verdict},
+    policy::LinkPolicy,
+    update_wating_user, user_finish,
 };
-use crate::{Bot, question, utils::*};
+use crate::{Bot, CONFIG, question, utils::*};
+
+fn policy() -> &'static LinkPolicy {
+    &CONFIG.get().unwrap().verification.link
+}
+
+/// Lets allowed links pass, deletes denied ones at once and asks a question for the unknown ones.
+pub async fn check_links(bot: Bot, msg: Message, links: Vec<Link>) -> Result<()> {
+    let user = if let Some(user) = msg.from.clone() {
+        user
+    } else {
+        return Ok(());
+    };
+    match link_lists::check(&links).await? {
+        Verdict::Allowed => Ok(()),
+        Verdict::Denied(link) => {
+            bot.delete_message(msg.chat.id, msg.id).await?;
+            admin_log(
+                bot,
+                format!(
+                    "已删除 {} 发送的黑名单链接：{}",
+                    metion_user(&user),
+                    htmlescape::encode_minimal(&link.to_string())
+                ),
+            )
+            .await
+        }
+        Verdict::Unknown => LinkHandler.send_question(bot, user, msg.chat.clone(), msg).await,
+    }
+}
+
+/// Copy of a spam message deleted while its question is pending.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HiddenMessage {
+    /// copied to `admin_log`, copied back once the user is verified
+    pub stash: Option<MessageId>,
+    /// text or caption as HTML, reposted when the copy fails
+    pub text: Option<String>,
+}
+
+/// Copies the message to `admin_log` and deletes it from the group.
+async fn hide(bot: &Bot, message: &Message, user: &User) -> Result<HiddenMessage> {
+    let admin_log = CONFIG.get().unwrap().admin_log;
+    let stash = bot
+        .copy_message(admin_log, message.chat.id, message.id)
+        .await
+        .map_err(|err| log::warn!("Failed to stash message {} of {}: {}", message.id, user.id, err))
+        .ok();
+    let text = message
+        .html_text()
+        .or_else(|| message.html_caption())
+        .or_else(|| message.text().or(message.caption()).map(htmlescape::encode_minimal));
+    // the message stays visible until it is deleted, so the notice waits
+    bot.delete_message(message.chat.id, message.id).await?;
+    if let Some(stash) = stash {
+        if let Err(err) = bot
+            .send_message(admin_log, format!("已隐藏 {} 等待验证的消息", metion_user(user)))
+            .reply_to_message_id(stash)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            log::warn!("Failed to send hidden notice of {}: {}", user.id, err);
+        }
+    }
+
+    Ok(HiddenMessage { stash, text })
+}
+
+/// Posts the hidden message again, attributed to the user.
+async fn repost(bot: &Bot, data: &QuestionData, hidden: &HiddenMessage) -> Result<()> {
+    let attribution = format!("以上消息来自 {}，已通过验证", metion_user(&data.user));
+    if let Some(stash) = hidden.stash {
+        match bot
+            .copy_message(data.chat_id, CONFIG.get().unwrap().admin_log, stash)
+            .await
+        {
+            Ok(copy) => {
+                bot.send_message(data.chat_id, attribution)
+                    .reply_to_message_id(copy)
+                    .parse_mode(ParseMode::Html)
+                    .await?;
+                return Ok(());
+            }
+            Err(err) => log::warn!("Failed to repost message of {}: {}", data.user.id, err),
+        }
+    }
+    if let Some(text) = hidden.text.as_ref() {
+        bot.send_message(data.chat_id, format!("{}：\n{}", metion_user(&data.user), text))
+            .parse_mode(ParseMode::Html)
+            .disable_web_page_preview()
+            .await?;
+    }
+    Ok(())
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct LinkHandler;
 
 impl Handler for LinkHandler {
-    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, message_id: MessageId) -> Result<()> {
-        if user.is_bot || user.is_premium || auth_database::is_authed(user.id.0).await? {
+    const NAME: &'static str = "Link";
+
+    type Id = Message;
+    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, message: Message) -> Result<()> {
+        if user.is_bot
+            || (user.is_premium && policy().premium_skip)
+            || auth_database::is_authed(user.id, chat.id).await?
+        {
             return Ok(());
         }
 
-        let (title, options, correct_idx) = question::new_question();
-
-        let data = QuestionData {
-            user: user.clone(),
-            chat_id: chat.id,
-            message_id: Some(message_id),
-            title,
-            options,
-            correct: correct_idx,
-            tried_times: 0,
-            cas: None,
-            left_minutes: 5,
-            handler: super::handler::HandlerKind::Link,
+        let kind = question::random_kind(policy().text_question_ratio, policy().image_question_ratio);
+        let deadline = Utc::now() + Duration::minutes(policy().timeout_minutes);
+        let mut data = QuestionData {
+            message_id: Some(message.id),
+            ..QuestionData::new(user.clone(), chat.id, Self::NAME, kind, deadline)
         };
+        data.renew_question();
 
-        let res = bot
-            .send_message(chat.id, data.message())
-            .parse_mode(ParseMode::Html)
-            .reply_to_message_id(message_id)
-            .reply_markup(data.keyboard(true))
-            .await;
+        let res = super::send_question_message(&bot, &data, Some(message.id)).await;
 
         let msg: Message = match res {
             Ok(msg) => msg,
             Err(err) => {
                 admin_log(bot.clone(), format!("问题发送失败，自动允许发送\n{}", err)).await?;
-                return Err(err.into());
+                return Err(err);
             }
         };
 
-        super::add_wating_user(msg.id, data).await;
-
-        let _handle = tokio::spawn(super::waiting_answer(bot.clone(), msg.id, |data| async move {
-            delete_sent_message(bot, data).await.ok();
-        }));
+        // hidden only once the question is out, a message hidden for a question that failed would be lost
+        if policy().hide_pending {
+            match hide(&bot, &message, &user).await {
+                Ok(hidden) => data.hidden = Some(hidden),
+                Err(err) => log::warn!("Failed to hide message {} of {}: {}", message.id, user.id, err),
+            }
+        }
 
-        // add_wating_handle(msg.id, handle.abort_handle()).await;
+        super::add_wating_user((msg.chat.id, msg.id), data).await?;
 
         Ok(())
     }
@@ -69,42 +166,45 @@ impl Handler for LinkHandler {
         ])
     }
 
-    async fn handle_correct(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
-        if let Some(data) = user_finish(msg_id).await {
+    async fn handle_correct(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        if let Some(data) = user_finish(key).await {
+            let info = AuthInfo::new(AuthMethod::Quiz, Some(data.1.chat_id));
+            auth_database::add_authed(data.1.user.id, Scope::of(data.1.chat_id), info).await?;
             allow_send_message(bot, data).await?;
         }
         res!("回答正确，验证通过")
     }
 
-    async fn handle_wrong(&mut self, bot: Bot, msg_id: MessageId) -> Result<Option<String>> {
+    async fn handle_wrong(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
         let tried_times = {
-            if let Some(data) = get_data_by_msg(&msg_id.0).await {
+            if let Some(data) = get_data_by_msg(&key).await {
                 data.tried_times
             } else {
                 return res!();
             }
         };
-        if tried_times >= 2 {
-            if let Some(data) = user_finish(msg_id).await {
+        if tried_times >= policy().max_wrong_attempts {
+            if let Some(data) = user_finish(key).await {
                 delete_sent_message(bot, data).await?;
             }
             res!("验证失败，失败次数过多，删除消息。")
         } else {
-            if let Some(mut data) = get_data_by_msg(&msg_id.0).await {
+            if let Some(mut data) = get_data_by_msg(&key).await {
                 data.tried_times += 1;
+                update_wating_user(key, &data).await;
             }
             res!("验证失败")
         }
     }
 
-    async fn handle_other(&mut self, bot: Bot, word: &str, msg_id: MessageId) -> Result<Option<String>> {
+    async fn handle_other(&mut self, bot: Bot, word: &str, key: QuestionKey) -> Result<Option<String>> {
         if word == "admin-ban" {
-            if let Some(data) = user_finish(msg_id).await {
+            if let Some(data) = user_finish(key).await {
                 delete_sent_message(bot, data).await?;
             }
             res!()
         } else if word == "admin-allow" {
-            if let Some(data) = user_finish(msg_id).await {
+            if let Some(data) = user_finish(key).await {
                 allow_send_message(bot, data).await?;
             }
             res!()
@@ -112,19 +212,27 @@ impl Handler for LinkHandler {
             res!(("未知命令：{}", word))
         }
     }
+
+    async fn handle_timeout(&mut self, bot: Bot, data: (QuestionKey, QuestionData)) -> Result<()> {
+        delete_sent_message(bot, data).await
+    }
 }
 
-async fn delete_sent_message(bot: Bot, (msg_id, data): (i32, QuestionData)) -> Result<()> {
-    super::TO_DELETE_MESSAGE.push((data.chat_id, MessageId(msg_id)));
-    if let Some(spam_msg_id) = data.message_id {
+async fn delete_sent_message(bot: Bot, (key, data): (QuestionKey, QuestionData)) -> Result<()> {
+    super::TO_DELETE_MESSAGE.push(key);
+    super::penalty::record(&data, "删除消息").await?;
+    if let Some(spam_msg_id) = data.message_id.filter(|_| data.hidden.is_none()) {
         bot.delete_message(data.chat_id, spam_msg_id).await?;
     }
 
     Ok(())
 }
 
-async fn allow_send_message(_bot: Bot, (msg_id, data): (i32, QuestionData)) -> Result<()> {
-    super::TO_DELETE_MESSAGE.push((data.chat_id, MessageId(msg_id)));
+async fn allow_send_message(bot: Bot, (key, data): (QuestionKey, QuestionData)) -> Result<()> {
+    super::TO_DELETE_MESSAGE.push(key);
+    if let Some(hidden) = data.hidden.as_ref() {
+        repost(&bot, &data, hidden).await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,308 @@
+//! Domains and usernames allowed or denied in links, from the config and edited at runtime in Redis.
+
+use std::fmt;
+
+use anyhow::Result;
+use redis::AsyncCommands;
+use teloxide::prelude::*;
+
+use super::link_detector::Link;
+use crate::{Bot, CONFIG, utils::EasySendMessage};
+
+const KEY_PREFIX: &str = "shit_bot_link_list";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum List {
+    Allow,
+    Deny,
+}
+
+impl List {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "allow" => Some(List::Allow),
+            "deny" => Some(List::Deny),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            List::Allow => "allow",
+            List::Deny => "deny",
+        }
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            List::Allow => "白名单",
+            List::Deny => "黑名单",
+        })
+    }
+}
+
+/// Entry of a list, a domain also covers its subdomains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Domain(String),
+    Username(String),
+}
+
+impl Entry {
+    /// `@username`, `t.me/username` or a domain, URLs are cut down to the host and `*.example.com` is the same as
+    /// `example.com`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_lowercase();
+        let s = s.split_once("://").map_or(s.as_str(), |(_, rest)| rest);
+        if let Some(username) = s.strip_prefix('@') {
+            return Some(Entry::Username(username.to_string()));
+        }
+        if let Some(username) = ["t.me/", "telegram.me/"]
+            .iter()
+            .find_map(|prefix| s.strip_prefix(prefix))
+        {
+            return Some(Entry::Username(username.trim_end_matches('/').to_string()));
+        }
+        let host = host(s).trim_start_matches("*.");
+        host.contains('.').then(|| Entry::Domain(host.to_string()))
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Entry::Domain(_) => "domain",
+            Entry::Username(_) => "username",
+        }
+    }
+
+    fn value(&self) -> &str {
+        match self {
+            Entry::Domain(value) | Entry::Username(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Domain(domain) => f.write_str(domain),
+            Entry::Username(username) => write!(f, "@{}", username),
+        }
+    }
+}
+
+/// Host of a URL without the scheme, e.g. `example.com` of `www.example.com:443/path`.
+fn host(url: &str) -> &str {
+    let end = url.find(['/', '?', '#']).unwrap_or(url.len());
+    let host = &url[..end];
+    let host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    host.trim_start_matches("www.")
+}
+
+fn key(list: List, kind: &str) -> String {
+    format!("{}:{}:{}", KEY_PREFIX, list.name(), kind)
+}
+
+fn configured(list: List) -> Vec<Entry> {
+    let policy = &CONFIG.get().unwrap().verification.link;
+    let (domains, usernames) = match list {
+        List::Allow => (&policy.allow_domains, &policy.allow_usernames),
+        List::Deny => (&policy.deny_domains, &policy.deny_usernames),
+    };
+    domains
+        .iter()
+        .filter_map(|domain| Entry::parse(domain))
+        .chain(
+            usernames
+                .iter()
+                .map(|username| Entry::Username(username.trim_start_matches('@').to_lowercase())),
+        )
+        .collect()
+}
+
+/// Entries added at runtime.
+pub async fn stored(list: List) -> Result<Vec<Entry>> {
+    let mut con = crate::get_connection().await;
+    let mut domains: Vec<String> = con.smembers(key(list, "domain")).await?;
+    let mut usernames: Vec<String> = con.smembers(key(list, "username")).await?;
+    domains.sort_unstable();
+    usernames.sort_unstable();
+    Ok(domains
+        .into_iter()
+        .map(Entry::Domain)
+        .chain(usernames.into_iter().map(Entry::Username))
+        .collect())
+}
+
+/// Entries of the config file and the ones added at runtime.
+pub async fn entries(list: List) -> Result<Vec<Entry>> {
+    let mut entries = configured(list);
+    entries.extend(stored(list).await?);
+    Ok(entries)
+}
+
+pub async fn add(list: List, entry: &Entry) -> Result<()> {
+    let mut con = crate::get_connection().await;
+    () = con.sadd(key(list, entry.kind()), entry.value()).await?;
+    Ok(())
+}
+
+/// Removes an entry added at runtime, the ones in the config file stay. Returns `false` if it was not stored.
+pub async fn remove(list: List, entry: &Entry) -> Result<bool> {
+    let mut con = crate::get_connection().await;
+    Ok(con.srem(key(list, entry.kind()), entry.value()).await?)
+}
+
+fn matches(entry: &Entry, link: &Link) -> bool {
+    match (entry, link) {
+        (Entry::Domain(domain), Link::Url(url)) => {
+            let host = host(url);
+            host == domain || host.ends_with(&format!(".{}", domain))
+        }
+        (Entry::Username(username), Link::Username(linked)) => username == linked,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// every link is allowed
+    Allowed,
+    /// the first link found in the deny list
+    Denied(Link),
+    Unknown,
+}
+
+/// A denied link wins over allowed ones, the message is allowed only if every link is.
+pub async fn check(links: &[Link]) -> Result<Verdict> {
+    let deny = entries(List::Deny).await?;
+    if let Some(link) = links.iter().find(|link| deny.iter().any(|entry| matches(entry, link))) {
+        return Ok(Verdict::Denied(link.clone()));
+    }
+    let allow = entries(List::Allow).await?;
+    if links.iter().all(|link| allow.iter().any(|entry| matches(entry, link))) {
+        Ok(Verdict::Allowed)
+    } else {
+        Ok(Verdict::Unknown)
+    }
+}
+
+const COMMAND_USAGE: &str = "用法：/linklist [allow|deny|remove] <域名或 @用户名>";
+
+async fn describe() -> Result<String> {
+    let mut text = Vec::new();
+    for list in [List::Allow, List::Deny] {
+        let configured = configured(list)
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<_>>();
+        let stored = stored(list)
+            .await?
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<_>>();
+        text.push(format!(
+            "{}：\n配置文件：{}\n运行时添加：{}",
+            list,
+            if configured.is_empty() {
+                "无".to_string()
+            } else {
+                configured.join("、")
+            },
+            if stored.is_empty() {
+                "无".to_string()
+            } else {
+                stored.join("、")
+            },
+        ));
+    }
+    Ok(text.join("\n\n"))
+}
+
+/// `/linklist` shows the lists, `/linklist allow|deny <entry>` adds to one and `/linklist remove <entry>` removes from
+/// both.
+pub async fn command(bot: Bot, message: &Message, arg: &str) -> Result<()> {
+    let words = arg.split_whitespace().collect::<Vec<_>>();
+    let text = match words.as_slice() {
+        [] => describe().await?,
+        [action, entry] => match (List::parse(action), Entry::parse(entry)) {
+            (_, None) => format!("无法识别的域名或用户名：{}", entry),
+            (Some(list), Some(entry)) => {
+                add(list, &entry).await?;
+                format!("已将 {} 加入链接{}", entry, list)
+            }
+            (None, Some(entry)) if *action == "remove" => {
+                let mut removed = Vec::new();
+                for list in [List::Allow, List::Deny] {
+                    if remove(list, &entry).await? {
+                        removed.push(list.to_string());
+                    }
+                }
+                if removed.is_empty() {
+                    format!("{} 不在运行时添加的名单中，配置文件中的条目需修改配置文件", entry)
+                } else {
+                    format!("已将 {} 移出链接{}", entry, removed.join("、"))
+                }
+            }
+            (None, Some(_)) => COMMAND_USAGE.to_string(),
+        },
+        _ => COMMAND_USAGE.to_string(),
+    };
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(domain: &str) -> Option<Entry> {
+        Some(Entry::Domain(domain.to_string()))
+    }
+
+    fn username(username: &str) -> Option<Entry> {
+        Some(Entry::Username(username.to_string()))
+    }
+
+    fn url(url: &str) -> Link {
+        Link::Url(url.to_string())
+    }
+
+    #[test]
+    fn parse_entries() {
+        assert_eq!(Entry::parse("Example.com"), domain("example.com"));
+        assert_eq!(
+            Entry::parse("https://www.example.com:443/path?q#top"),
+            domain("example.com")
+        );
+        assert_eq!(Entry::parse("*.example.com"), domain("example.com"));
+        assert_eq!(Entry::parse("https://*.example.com/"), domain("example.com"));
+        assert_eq!(Entry::parse("@Spam_Bot"), username("spam_bot"));
+        assert_eq!(Entry::parse("t.me/spam_bot/"), username("spam_bot"));
+        assert_eq!(Entry::parse("https://telegram.me/spam_bot"), username("spam_bot"));
+        assert_eq!(Entry::parse("localhost"), None);
+        assert_eq!(Entry::parse("  "), None);
+    }
+
+    #[test]
+    fn domains_cover_subdomains() {
+        let entry = Entry::parse("*.example.com").unwrap();
+        assert!(matches(&entry, &url("example.com")));
+        assert!(matches(&entry, &url("www.example.com/path")));
+        assert!(matches(&entry, &url("cdn.img.example.com:8080")));
+        assert!(!matches(&entry, &url("notexample.com")));
+        assert!(!matches(&entry, &url("example.com.evil.xyz")));
+        assert!(!matches(&entry, &Link::Username("example".to_string())));
+    }
+
+    #[test]
+    fn usernames_match_exactly() {
+        let entry = Entry::parse("@spam_bot").unwrap();
+        assert!(matches(&entry, &Link::Username("spam_bot".to_string())));
+        assert!(!matches(&entry, &Link::Username("spam_bot2".to_string())));
+        assert!(!matches(&entry, &url("t.me/spam_bot")));
+    }
+}
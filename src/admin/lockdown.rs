@@ -0,0 +1,329 @@
+//! Join raid detection and the lockdown it puts the group into.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, Utc};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    prelude::*,
+    types::{ChatMemberUpdated, ChatPermissions, InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, User},
+};
+
+use super::{
+    auth_database,
+    policy::LockdownAction,
+    scheduler::{self, Job},
+};
+use crate::{Bot, CONFIG, utils::*};
+
+const RECENT_JOINS_KEY: &str = "shit_bot_recent_joins";
+const LOCKDOWN_KEY: &str = "shit_bot_lockdown";
+const HELD_USERS_KEY: &str = "shit_bot_lockdown_held";
+
+pub const END_CALLBACK: &str = "lockdown-end";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockdownState {
+    pub reason: String,
+    pub since: DateTime<Utc>,
+    /// default permissions of the group before the lockdown, restored afterwards
+    pub permissions: Option<ChatPermissions>,
+    /// lifted automatically at this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+pub async fn state() -> Result<Option<LockdownState>> {
+    let mut con = crate::get_connection().await;
+    let raw: Option<String> = con.get(LOCKDOWN_KEY).await?;
+    Ok(raw.map(|raw| serde_json::from_str(&raw)).transpose()?)
+}
+
+pub async fn is_locked() -> bool {
+    match state().await {
+        Ok(state) => state.is_some(),
+        Err(err) => {
+            log::error!("Failed to read lockdown state: {}", err);
+            false
+        }
+    }
+}
+
+fn schedule_end(until: DateTime<Utc>) {
+    scheduler::schedule(Job::EndLockdown, (until - Utc::now()).to_std().unwrap_or_default());
+}
+
+/// Schedules the automatic end of a lockdown started before a restart.
+pub async fn resume() -> Result<()> {
+    if let Some(until) = state().await?.and_then(|state| state.until) {
+        schedule_end(until);
+    }
+    Ok(())
+}
+
+/// Parses durations like `30m`, `2h`, `1d` or `1h30m`, plain numbers are minutes.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if let Ok(minutes) = s.parse::<i64>() {
+        return Duration::try_minutes(minutes).filter(|duration| *duration > Duration::zero());
+    }
+    let mut total = Duration::zero();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let idx = rest.find(|c: char| !c.is_ascii_digit())?;
+        let (number, tail) = rest.split_at(idx);
+        let number = number.parse::<i64>().ok().filter(|n| *n > 0)?;
+        let part = match tail.as_bytes()[0] {
+            b's' => Duration::try_seconds(number),
+            b'm' => Duration::try_minutes(number),
+            b'h' => Duration::try_hours(number),
+            b'd' => Duration::try_days(number),
+            _ => None,
+        }?;
+        total = total.checked_add(&part)?;
+        rest = &tail[1..];
+    }
+    (total > Duration::zero()).then_some(total)
+}
+
+/// Counts the join in the sliding window and locks the group down once the threshold is reached. Authed members
+/// coming back are not counted.
+pub async fn record_join(bot: Bot, update: &ChatMemberUpdated) -> Result<()> {
+    let policy = &CONFIG.get().unwrap().verification.raid;
+    let user = &update.new_chat_member.user;
+    if !policy.enabled || user.is_bot || auth_database::is_authed(user.id, update.chat.id).await? {
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp_millis();
+    let window_start = now - policy.window_seconds * 1000;
+    let joins: u32 = {
+        let mut con = crate::get_connection().await;
+        () = con.zadd(RECENT_JOINS_KEY, user.id.0, now).await?;
+        () = con.zrembyscore(RECENT_JOINS_KEY, "-inf", window_start).await?;
+        () = con.expire(RECENT_JOINS_KEY, policy.window_seconds).await?;
+        con.zcard(RECENT_JOINS_KEY).await?
+    };
+
+    if joins >= policy.join_threshold && !is_locked().await {
+        start(
+            bot,
+            update.chat.id,
+            format!("{} 秒内有 {} 个账号加入", policy.window_seconds, joins),
+            None,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Snapshots and tightens the default permissions, then reports to `admin_log`. Does nothing if already locked.
+pub async fn start(bot: Bot, chat_id: ChatId, reason: String, until: Option<DateTime<Utc>>) -> Result<bool> {
+    let permissions = bot.get_chat(chat_id).await?.permissions();
+    let state = LockdownState {
+        reason: reason.clone(),
+        since: Utc::now(),
+        permissions: permissions.clone(),
+        until,
+    };
+    let locked: bool = {
+        let mut con = crate::get_connection().await;
+        con.set_nx(LOCKDOWN_KEY, serde_json::to_string(&state)?).await?
+    };
+    if !locked {
+        return Ok(false);
+    }
+    if let Some(until) = until {
+        schedule_end(until);
+    }
+
+    let tightened = permissions.unwrap_or(ChatPermissions::all()) & ChatPermissions::SEND_MESSAGES;
+    let result = match bot.set_chat_permissions(chat_id, tightened).await {
+        Ok(_) => "已收紧群组默认权限".to_string(),
+        Err(err) => format!("收紧群组默认权限失败：{}", err),
+    };
+    let action = match CONFIG.get().unwrap().verification.raid.action {
+        LockdownAction::Kick => "新加入的未验证用户将被踢出",
+        LockdownAction::Hold => "新加入的未验证用户将被禁言，解除后再验证",
+    };
+    let until = match until {
+        Some(until) => format!("\n将于 {} 自动解除", until.with_timezone(&Local).format("%m-%d %H:%M")),
+        None => String::new(),
+    };
+    let keyboard = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback("解除封锁🔓", END_CALLBACK)]]);
+    bot.send_message(
+        CONFIG.get().unwrap().admin_log,
+        format!("🚨群组已进入封锁状态：{}\n{}，{}{}", reason, result, action, until),
+    )
+    .reply_markup(keyboard)
+    .await?;
+
+    Ok(true)
+}
+
+/// Restores the permissions and verifies the users held during the lockdown. Returns `false` if not locked.
+pub async fn end(bot: Bot) -> Result<bool> {
+    let chat_id = CONFIG.get().unwrap().manage_chat;
+    let (raw, held): (Option<String>, Vec<String>) = {
+        let mut con = crate::get_connection().await;
+        let raw = con.get_del(LOCKDOWN_KEY).await?;
+        let held = con.hvals(HELD_USERS_KEY).await?;
+        () = con.del(HELD_USERS_KEY).await?;
+        (raw, held)
+    };
+    let state: LockdownState = match raw {
+        Some(raw) => serde_json::from_str(&raw)?,
+        None => return Ok(false),
+    };
+    scheduler::cancel(Job::EndLockdown);
+
+    if let Some(permissions) = state.permissions {
+        if let Err(err) = bot.set_chat_permissions(chat_id, permissions).await {
+            admin_log(bot.clone(), format!("恢复群组默认权限失败，请管理员手动恢复：{}", err)).await?;
+        }
+    }
+
+    for raw in held {
+        let (user, chat_id): (User, ChatId) = match serde_json::from_str(&raw) {
+            Ok(held) => held,
+            Err(err) => {
+                log::error!("Dropping broken held user {}: {}", raw, err);
+                continue;
+            }
+        };
+        let res = super::join_handler::verify(bot.clone(), user, chat_id, true).await;
+        if let Err(err) = res {
+            admin_log(bot.clone(), format!("{}", err)).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Kicks or holds an unauthed user joining during the lockdown, returns `true` if the join was handled here.
+pub async fn intercept_join(bot: &Bot, user: &User, chat_id: ChatId) -> Result<bool> {
+    if !is_locked().await {
+        return Ok(false);
+    }
+
+    match CONFIG.get().unwrap().verification.raid.action {
+        LockdownAction::Kick => {
+            let mut req = bot.ban_chat_member(chat_id, user.id);
+            req.until_date = Some(Utc::now() + Duration::minutes(1));
+            req.await?;
+        }
+        LockdownAction::Hold => {
+            bot.restrict_chat_member(chat_id, user.id, ChatPermissions::empty())
+                .await?;
+            let mut con = crate::get_connection().await;
+            () = con
+                .hset(HELD_USERS_KEY, user.id.0, serde_json::to_string(&(user, chat_id))?)
+                .await?;
+        }
+    }
+    Ok(true)
+}
+
+pub async fn end_callback(bot: Bot, callback: CallbackQuery) -> Result<()> {
+    if !crate::is_privileged(&bot, callback.from.id).await {
+        bot.answer_callback_query(callback.id)
+            .text("只有管理员可以点击此按钮")
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    }
+
+    let ended = end(bot.clone()).await?;
+    bot.answer_callback_query(callback.id)
+        .text(if ended {
+            "已解除封锁"
+        } else {
+            "群组未处于封锁状态"
+        })
+        .await?;
+    if let Some(message) = callback.message {
+        let text = message.regular_message().and_then(|msg| msg.text()).unwrap_or_default();
+        bot.edit_message_text(
+            message.chat().id,
+            message.id(),
+            format!(
+                "{}\n\n{} 已解除封锁",
+                htmlescape::encode_minimal(text),
+                metion_user(&callback.from)
+            ),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(InlineKeyboardMarkup::default())
+        .await?;
+    }
+    Ok(())
+}
+
+/// Unauthed members can not talk during a lockdown, their messages are deleted.
+pub async fn should_delete(bot: &Bot, msg: &Message) -> bool {
+    let user = match msg.from.as_ref() {
+        Some(user) => user,
+        None => return false,
+    };
+    // anonymous admins send as the group itself
+    if msg.chat.id != CONFIG.get().unwrap().manage_chat || msg.sender_chat.is_some() || !is_locked().await {
+        return false;
+    }
+    match auth_database::is_authed(user.id, msg.chat.id).await {
+        Ok(true) => return false,
+        Ok(false) => {}
+        Err(err) => {
+            log::error!("Failed to check authed user: {}", err);
+            return false;
+        }
+    }
+    !bot.get_chat_member(msg.chat.id, user.id)
+        .await
+        .map(|c| c.is_privileged())
+        .unwrap_or(false)
+}
+
+pub async fn delete_message(bot: Bot, msg: Message) -> Result<()> {
+    bot.delete_message(msg.chat.id, msg.id).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        for (s, minutes) in [
+            ("30", 30),
+            ("30m", 30),
+            (" 2h ", 120),
+            ("1d", 24 * 60),
+            ("1h30m", 90),
+            ("1d2h", 26 * 60),
+        ] {
+            assert_eq!(parse_duration(s), Some(Duration::minutes(minutes)), "{}", s);
+        }
+        assert_eq!(parse_duration("45s"), Some(Duration::seconds(45)));
+    }
+
+    #[test]
+    fn reject_bad_durations() {
+        for s in [
+            "",
+            "0",
+            "0m",
+            "-5",
+            "h",
+            "5x",
+            "1h30",
+            "1.5h",
+            "1 h",
+            "30分钟",
+            "9999999999999999d",
+        ] {
+            assert_eq!(parse_duration(s), None, "{}", s);
+        }
+    }
+}
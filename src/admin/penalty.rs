@@ -0,0 +1,128 @@
+//! Failure history of users, escalating the ban of repeat offenders.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Local, Utc};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, UserId};
+
+use super::{QuestionData, handler::Handler, join_handler::JoinHandler};
+use crate::CONFIG;
+
+const HISTORY_KEY_PREFIX: &str = "shit_bot_failures";
+// older failures are dropped from the history
+const MAX_HISTORY: isize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub time: DateTime<Utc>,
+    pub handler: String,
+    pub chat_id: ChatId,
+    /// title of the failed question, empty when there was none
+    pub question: String,
+    /// what was done to the user, shown to the admins
+    pub penalty: String,
+}
+
+fn history_key(user_id: UserId) -> String {
+    format!("{}:{}", HISTORY_KEY_PREFIX, user_id)
+}
+
+pub async fn record(data: &QuestionData, penalty: impl Into<String>) -> Result<()> {
+    let failure = Failure {
+        time: Utc::now(),
+        handler: data.handler.clone(),
+        chat_id: data.chat_id,
+        question: data.title.clone(),
+        penalty: penalty.into(),
+    };
+    let key = history_key(data.user.id);
+    let mut con = crate::get_connection().await;
+    () = con.lpush(&key, serde_json::to_string(&failure)?).await?;
+    () = con.ltrim(&key, 0, MAX_HISTORY - 1).await?;
+    // only cleans up, the old failures of a list kept alive by new ones are skipped by `history`
+    let days = CONFIG.get().unwrap().verification.penalty.forget_after_days;
+    if days > 0 {
+        () = con.expire(&key, days * 24 * 3600).await?;
+    }
+    Ok(())
+}
+
+/// Failures of the user not forgotten yet, the latest first.
+pub async fn history(user_id: UserId) -> Result<Vec<Failure>> {
+    let mut con = crate::get_connection().await;
+    let raw: Vec<String> = con.lrange(history_key(user_id), 0, -1).await?;
+    let days = CONFIG.get().unwrap().verification.penalty.forget_after_days;
+    let since = Utc::now() - Duration::days(days);
+    Ok(raw
+        .iter()
+        .filter_map(|raw| match serde_json::from_str::<Failure>(raw) {
+            Ok(failure) => Some(failure),
+            Err(err) => {
+                log::error!("Dropping broken failure record {}: {}", raw, err);
+                None
+            }
+        })
+        .filter(|failure| days <= 0 || failure.time > since)
+        .collect())
+}
+
+/// Ban length of the next failed join verification in minutes, 0 for a permanent ban. Only the earlier join failures
+/// count, deleted links or declined requests do not escalate it.
+pub async fn next_ban_minutes(user_id: UserId) -> Result<i64> {
+    let failures = history(user_id)
+        .await?
+        .iter()
+        .filter(|failure| failure.handler == <JoinHandler as Handler>::NAME)
+        .count();
+    let verification = &CONFIG.get().unwrap().verification;
+    let escalation = &verification.penalty.escalation_minutes;
+    Ok(match failures.checked_sub(1) {
+        Some(step) if !escalation.is_empty() => escalation[step.min(escalation.len() - 1)],
+        _ => verification.join.ban_minutes,
+    })
+}
+
+/// `until_date` of a ban lasting `minutes`.
+pub fn ban_until(minutes: i64) -> Option<DateTime<Utc>> {
+    (minutes > 0).then(|| Utc::now() + Duration::minutes(minutes))
+}
+
+pub fn describe_ban(minutes: i64) -> String {
+    if minutes > 0 {
+        format!("封禁 {} 分钟", minutes)
+    } else {
+        "永久封禁".to_string()
+    }
+}
+
+/// History as HTML for the admins.
+pub fn format_history(user_id: UserId, failures: &[Failure]) -> String {
+    if failures.is_empty() {
+        return format!("用户 <code>{}</code> 没有验证失败记录", user_id);
+    }
+    let lines = failures
+        .iter()
+        .map(|failure| {
+            let question = if failure.question.is_empty() {
+                String::new()
+            } else {
+                format!("「{}」", htmlescape::encode_minimal(&failure.question))
+            };
+            format!(
+                "{} {}{} → {}",
+                failure.time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                failure.handler,
+                question,
+                htmlescape::encode_minimal(&failure.penalty)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "用户 <code>{}</code> 共有 {} 次验证失败记录：\n{}",
+        user_id,
+        failures.len(),
+        lines
+    )
+}
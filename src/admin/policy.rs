@@ -0,0 +1,198 @@
+use serde::Deserialize;
+use teloxide::types::ChatId;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VerificationConfig {
+    pub join: JoinPolicy,
+    pub link: LinkPolicy,
+    pub join_request: JoinRequestPolicy,
+    pub raid: RaidPolicy,
+    pub channels: ChannelPolicy,
+    pub penalty: PenaltyPolicy,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JoinPolicy {
+    /// minutes the user has to answer
+    pub timeout_minutes: i64,
+    /// wrong answers tolerated, the next one fails the verification
+    pub max_wrong_attempts: u8,
+    /// ban length after the first failure, see `PenaltyPolicy` for the following ones
+    pub ban_minutes: i64,
+    /// ban length for users not in the required channels
+    pub master_channel_kick_minutes: i64,
+    pub premium_skip: bool,
+    /// probability of asking a free-text question instead of a choice one
+    pub text_question_ratio: f64,
+    /// probability of asking a rendered image captcha, checked before the text ratio
+    pub image_question_ratio: f64,
+    /// post a deep link button in the group and ask the question in private chat
+    pub private_quiz: bool,
+    /// ban users leaving before finishing the verification, as long as a failed answer would
+    pub ban_on_leave: bool,
+}
+
+impl Default for JoinPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_minutes: 5,
+            max_wrong_attempts: 2,
+            ban_minutes: 10,
+            master_channel_kick_minutes: 1,
+            premium_skip: true,
+            text_question_ratio: 0.0,
+            image_question_ratio: 0.0,
+            private_quiz: false,
+            ban_on_leave: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LinkPolicy {
+    /// minutes the user has to answer before the message is deleted
+    pub timeout_minutes: i64,
+    /// wrong answers tolerated, the next one deletes the message
+    pub max_wrong_attempts: u8,
+    pub premium_skip: bool,
+    /// probability of asking a free-text question instead of a choice one
+    pub text_question_ratio: f64,
+    /// probability of asking a rendered image captcha, checked before the text ratio
+    pub image_question_ratio: f64,
+    /// links to these domains and their subdomains pass without a question, more can be added with `/linklist`
+    pub allow_domains: Vec<String>,
+    /// links to these domains are deleted at once
+    pub deny_domains: Vec<String>,
+    pub allow_usernames: Vec<String>,
+    pub deny_usernames: Vec<String>,
+    /// delete the message at once, it is reposted by the bot once the user answers correctly
+    pub hide_pending: bool,
+}
+
+impl Default for LinkPolicy {
+    fn default() -> Self {
+        Self {
+            timeout_minutes: 5,
+            max_wrong_attempts: 2,
+            premium_skip: true,
+            text_question_ratio: 0.0,
+            image_question_ratio: 0.0,
+            allow_domains: vec![],
+            deny_domains: vec![],
+            allow_usernames: vec![],
+            deny_usernames: vec![],
+            hide_pending: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JoinRequestPolicy {
+    /// answer join requests with a quiz in private chat, the mute flow of `join` still covers plain joins
+    pub enabled: bool,
+    /// minutes the user has to answer before the request is declined
+    pub timeout_minutes: i64,
+    /// wrong answers tolerated, the next one declines the request
+    pub max_wrong_attempts: u8,
+    pub premium_skip: bool,
+    /// probability of asking a free-text question instead of a choice one
+    pub text_question_ratio: f64,
+    /// probability of asking a rendered image captcha, checked before the text ratio
+    pub image_question_ratio: f64,
+}
+
+impl Default for JoinRequestPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_minutes: 5,
+            max_wrong_attempts: 2,
+            premium_skip: true,
+            text_question_ratio: 0.0,
+            image_question_ratio: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockdownAction {
+    /// kick new joiners, they can join again once the lockdown is over
+    #[default]
+    Kick,
+    /// mute new joiners and verify them once the lockdown is over
+    Hold,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RaidPolicy {
+    /// lock the group down when too many accounts join at once
+    pub enabled: bool,
+    /// joins within the window that trigger the lockdown
+    pub join_threshold: u32,
+    pub window_seconds: i64,
+    /// what happens to unauthed users joining during a lockdown
+    pub action: LockdownAction,
+}
+
+impl Default for RaidPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            join_threshold: 10,
+            window_seconds: 60,
+            action: LockdownAction::Kick,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequiredChannel {
+    pub id: ChatId,
+    pub title: Option<String>,
+    /// shown as a join button, e.g. `https://t.me/nipple_hill`
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelMode {
+    Any,
+    #[default]
+    All,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ChannelPolicy {
+    /// channels joining users must be in, `master_channel` when empty
+    pub required: Vec<RequiredChannel>,
+    pub mode: ChannelMode,
+    /// minutes a muted user has to join the channels before being kicked, 0 kicks at once
+    pub grace_minutes: i64,
+    /// tell the user in private chat instead of the group, falls back to the group
+    pub notice_in_private: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PenaltyPolicy {
+    /// ban lengths after the second, third... failure, 0 is permanent and the last one repeats
+    pub escalation_minutes: Vec<i64>,
+    /// failures older than this are forgotten, 0 keeps them forever
+    pub forget_after_days: i64,
+}
+
+impl Default for PenaltyPolicy {
+    fn default() -> Self {
+        Self {
+            escalation_minutes: vec![24 * 60, 0],
+            forget_after_days: 30,
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Reputation of joining users, combined from CAS, HTTP blocklists and the local ban list.
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use futures::future::{BoxFuture, join_all};
+use redis::AsyncCommands;
+use reqwest::Url;
+use serde::Deserialize;
+use teloxide::types::UserId;
+
+use crate::{Bot, CONFIG, utils::admin_log};
+
+const CACHE_KEY_PREFIX: &str = "shit_bot_reputation";
+const FAILURE_KEY_PREFIX: &str = "shit_bot_reputation_failure";
+// a failing provider is reported at most once in this many seconds
+const FAILURE_REPORT_INTERVAL: u64 = 600;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReputationConfig {
+    /// combined weight of the listing providers needed to flag a user
+    pub threshold: f64,
+    pub providers: Vec<ProviderConfig>,
+    /// how long a lookup result is cached, the periodic rescan ignores it
+    pub cache_ttl_seconds: u64,
+    /// retries of a failed lookup, the backoff doubles after each one
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+    /// interval of re-checking pending and recently admitted users, 0 disables it
+    pub rescan_minutes: u64,
+    /// admitted users are re-checked for this long
+    pub rescan_admitted_hours: i64,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            providers: vec![ProviderConfig {
+                kind: ProviderKind::Cas(CasProvider::default()),
+                weight: default_weight(),
+                timeout_ms: default_timeout_ms(),
+            }],
+            cache_ttl_seconds: 3600,
+            retries: 2,
+            retry_backoff_ms: 500,
+            rescan_minutes: 10,
+            rescan_admitted_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderConfig {
+    #[serde(flatten)]
+    pub kind: ProviderKind,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProviderKind {
+    Cas(CasProvider),
+    Http(HttpProvider),
+    Redis(RedisProvider),
+}
+
+impl ProviderKind {
+    fn provider(&self) -> &dyn Provider {
+        match self {
+            ProviderKind::Cas(provider) => provider,
+            ProviderKind::Http(provider) => provider,
+            ProviderKind::Redis(provider) => provider,
+        }
+    }
+}
+
+pub trait Provider: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Whether the user is listed by this provider.
+    fn check(&self, user_id: UserId) -> BoxFuture<'_, Result<bool>>;
+
+    /// Page describing the listing, shown to the admins.
+    fn link(&self, _user_id: UserId) -> Option<String> {
+        None
+    }
+
+    /// Local lookups are cheap and should see changes at once.
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CasProvider {
+    pub base_url: String,
+    pub query_url: String,
+}
+
+impl Default for CasProvider {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.cas.chat".to_string(),
+            query_url: "https://cas.chat/query".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+struct CasResult {
+    pub ok: bool,
+}
+
+impl Provider for CasProvider {
+    fn name(&self) -> &str {
+        "CAS"
+    }
+
+    fn check(&self, user_id: UserId) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async move {
+            let url = Url::parse_with_params(
+                &format!("{}/check", self.base_url.trim_end_matches('/')),
+                &[("user_id", user_id.to_string())],
+            )?;
+            Ok(reqwest::get(url).await?.json::<CasResult>().await?.ok)
+        })
+    }
+
+    fn link(&self, user_id: UserId) -> Option<String> {
+        Url::parse_with_params(&self.query_url, &[("u", user_id.to_string())])
+            .ok()
+            .map(String::from)
+    }
+}
+
+/// Queried with `user_id` as parameter, the user is listed when `field` of the JSON response is `true`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpProvider {
+    pub name: String,
+    pub url: String,
+    #[serde(default = "default_field")]
+    pub field: String,
+}
+
+fn default_field() -> String {
+    "banned".to_string()
+}
+
+impl Provider for HttpProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, user_id: UserId) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async move {
+            let url = Url::parse_with_params(&self.url, &[("user_id", user_id.to_string())])?;
+            let response = reqwest::get(url).await?.error_for_status()?;
+            let body = response.json::<serde_json::Value>().await?;
+            Ok(body.get(&self.field).and_then(|v| v.as_bool()).unwrap_or(false))
+        })
+    }
+}
+
+/// Users in a Redis set, e.g. banned by hand in other groups.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedisProvider {
+    pub key: String,
+}
+
+impl Default for RedisProvider {
+    fn default() -> Self {
+        Self {
+            key: "shit_bot_banned_users".to_string(),
+        }
+    }
+}
+
+impl Provider for RedisProvider {
+    fn name(&self) -> &str {
+        "本地封禁列表"
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn check(&self, user_id: UserId) -> BoxFuture<'_, Result<bool>> {
+        Box::pin(async move {
+            let mut con = crate::get_connection().await;
+            Ok(con.sismember(&self.key, user_id.0).await?)
+        })
+    }
+}
+
+/// Provider that listed the user.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub name: String,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Verdict {
+    pub score: f64,
+    pub hits: Vec<Hit>,
+    /// providers that failed or timed out, with the reason
+    pub errors: Vec<(String, String)>,
+}
+
+impl Verdict {
+    pub fn flagged(&self) -> bool {
+        !self.hits.is_empty() && self.score >= CONFIG.get().unwrap().reputation.threshold
+    }
+
+    /// Hits as HTML, linked where the provider has a page.
+    pub fn sources(&self) -> String {
+        self.hits
+            .iter()
+            .map(|hit| match &hit.link {
+                Some(link) => format!("<a href=\"{}\">{}</a>", link, htmlescape::encode_minimal(&hit.name)),
+                None => htmlescape::encode_minimal(&hit.name),
+            })
+            .collect::<Vec<_>>()
+            .join("、")
+    }
+}
+
+/// Cached lookup of one provider, retried with backoff on failure. `bypass_cache` queries the provider anyway and
+/// refreshes the cache.
+async fn check_provider(config: &ProviderConfig, user_id: UserId, bypass_cache: bool) -> Result<bool> {
+    let reputation = &CONFIG.get().unwrap().reputation;
+    let provider = config.kind.provider();
+    let cache_key = format!("{}:{}:{}", CACHE_KEY_PREFIX, provider.name(), user_id);
+    if provider.cacheable() && !bypass_cache {
+        let mut con = crate::get_connection().await;
+        let cached: Option<bool> = con.get(&cache_key).await?;
+        if let Some(listed) = cached {
+            return Ok(listed);
+        }
+    }
+
+    let mut backoff = Duration::from_millis(reputation.retry_backoff_ms);
+    let mut attempt = 0;
+    loop {
+        let err = match tokio::time::timeout(Duration::from_millis(config.timeout_ms), provider.check(user_id)).await {
+            Ok(Ok(listed)) => {
+                if provider.cacheable() {
+                    let mut con = crate::get_connection().await;
+                    () = con.set_ex(&cache_key, listed, reputation.cache_ttl_seconds).await?;
+                }
+                return Ok(listed);
+            }
+            Ok(Err(err)) => err,
+            Err(_) => anyhow!("超时"),
+        };
+        if attempt >= reputation.retries {
+            return Err(err);
+        }
+        attempt += 1;
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Queries every configured provider at once, each one bounded by its own timeout. Rescans bypass the cache, or a
+/// late listing would stay unseen until the cached result expires.
+pub async fn check(user_id: UserId, bypass_cache: bool) -> Verdict {
+    let providers = &CONFIG.get().unwrap().reputation.providers;
+    let results = join_all(
+        providers
+            .iter()
+            .map(|config| async move { (config, check_provider(config, user_id, bypass_cache).await) }),
+    )
+    .await;
+
+    let mut verdict = Verdict::default();
+    for (config, res) in results {
+        let provider = config.kind.provider();
+        match res {
+            Ok(true) => {
+                verdict.score += config.weight;
+                verdict.hits.push(Hit {
+                    name: provider.name().to_string(),
+                    link: provider.link(user_id),
+                });
+            }
+            Ok(false) => {}
+            Err(err) => verdict.errors.push((provider.name().to_string(), err.to_string())),
+        }
+    }
+    verdict
+}
+
+/// Reports providers that failed even after the retries to `admin_log`, once per provider in a while.
+pub async fn report_errors(bot: Bot, verdict: &Verdict) -> Result<()> {
+    for (provider, err) in &verdict.errors {
+        let first: bool = {
+            let mut con = crate::get_connection().await;
+            redis::cmd("SET")
+                .arg(format!("{}:{}", FAILURE_KEY_PREFIX, provider))
+                .arg(1)
+                .arg("NX")
+                .arg("EX")
+                .arg(FAILURE_REPORT_INTERVAL)
+                .query_async::<Option<String>>(&mut con)
+                .await?
+                .is_some()
+        };
+        if first {
+            admin_log(bot.clone(), format!("⚠️信誉查询 {} 持续失败：{}", provider, err)).await?;
+        }
+    }
+    Ok(())
+}
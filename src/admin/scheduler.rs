@@ -0,0 +1,167 @@
+//! One hashed timer wheel owning every deadline of the bot, instead of a sleeping task per question.
+
+use std::{collections::HashMap, time::Duration};
+
+use teloxide::{
+    prelude::*,
+    types::{ChatId, MessageId},
+};
+use tokio::{
+    sync::{
+        OnceCell,
+        mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    },
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::Bot;
+
+const TICK: Duration = Duration::from_secs(1);
+const SLOTS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Job {
+    /// Countdown edit or timeout of the question message
+    Question(ChatId, MessageId),
+    /// Removes a short-lived notice
+    DeleteMessage(ChatId, MessageId),
+    /// Lifts a lockdown started with a duration
+    EndLockdown,
+    /// Re-checks the reputation of pending and recently admitted users, then runs again
+    RescanReputation,
+}
+
+enum Command {
+    Schedule(Job, Duration),
+    Cancel(Job),
+}
+
+struct Entry {
+    job: Job,
+    rounds: u64,
+}
+
+struct TimerWheel {
+    slots: Vec<Vec<Entry>>,
+    cursor: usize,
+    // job -> slot, so that cancelling does not scan the whole wheel
+    positions: HashMap<Job, usize>,
+}
+
+impl TimerWheel {
+    fn new() -> Self {
+        Self {
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            cursor: 0,
+            positions: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, job: Job, delay: Duration) {
+        self.cancel(job);
+        let ticks = delay.as_millis().div_ceil(TICK.as_millis()).max(1) as u64;
+        let slot = (self.cursor + ticks as usize % SLOTS) % SLOTS;
+        let rounds = (ticks - 1) / SLOTS as u64;
+        self.slots[slot].push(Entry { job, rounds });
+        self.positions.insert(job, slot);
+    }
+
+    fn cancel(&mut self, job: Job) {
+        if let Some(slot) = self.positions.remove(&job) {
+            self.slots[slot].retain(|entry| entry.job != job);
+        }
+    }
+
+    fn tick(&mut self) -> Vec<Job> {
+        self.cursor = (self.cursor + 1) % SLOTS;
+        let mut expired = Vec::new();
+        self.slots[self.cursor].retain_mut(|entry| {
+            if entry.rounds == 0 {
+                expired.push(entry.job);
+                false
+            } else {
+                entry.rounds -= 1;
+                true
+            }
+        });
+        for job in &expired {
+            self.positions.remove(job);
+        }
+        expired
+    }
+}
+
+static SCHEDULER: OnceCell<UnboundedSender<Command>> = OnceCell::const_new();
+
+/// Starts the scheduler task, must be called before any job is scheduled.
+pub fn start(bot: Bot) {
+    let (tx, rx) = unbounded_channel();
+    SCHEDULER.set(tx).expect("Scheduler already started");
+    tokio::spawn(run(bot, rx));
+    schedule(Job::RescanReputation, Duration::from_secs(60));
+}
+
+/// Runs `job` after `delay`, replacing the previous schedule of the same job.
+pub fn schedule(job: Job, delay: Duration) {
+    send(Command::Schedule(job, delay));
+}
+
+pub fn cancel(job: Job) {
+    send(Command::Cancel(job));
+}
+
+fn send(command: Command) {
+    let sent = SCHEDULER.get().map(|tx| tx.send(command).is_ok()).unwrap_or(false);
+    if !sent {
+        log::error!("Scheduler is not running");
+    }
+}
+
+async fn run(bot: Bot, mut rx: UnboundedReceiver<Command>) {
+    let mut wheel = TimerWheel::new();
+    let mut ticker = interval(TICK);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                for job in wheel.tick() {
+                    tokio::spawn(fire(bot.clone(), job));
+                }
+            }
+            command = rx.recv() => match command {
+                Some(Command::Schedule(job, delay)) => wheel.insert(job, delay),
+                Some(Command::Cancel(job)) => wheel.cancel(job),
+                None => break,
+            },
+        }
+    }
+}
+
+async fn fire(bot: Bot, job: Job) {
+    match job {
+        Job::Question(chat_id, msg_id) => super::countdown(bot, (chat_id, msg_id)).await,
+        Job::DeleteMessage(chat_id, msg_id) => {
+            bot.delete_message(chat_id, msg_id).await.ok();
+        }
+        Job::RescanReputation => {
+            let minutes = crate::CONFIG.get().unwrap().reputation.rescan_minutes;
+            if minutes == 0 {
+                return;
+            }
+            schedule(Job::RescanReputation, Duration::from_secs(minutes * 60));
+            if let Err(err) = super::join_handler::rescan_reputation(bot).await {
+                log::error!("Failed to rescan reputation: {}", err);
+            }
+        }
+        Job::EndLockdown => match super::lockdown::end(bot.clone()).await {
+            Ok(true) => {
+                crate::utils::admin_log(bot, "封锁时间已到，已自动解除".to_string())
+                    .await
+                    .ok();
+            }
+            Ok(false) => {}
+            Err(err) => log::error!("Failed to end lockdown: {}", err),
+        },
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use rand::{Rng, distr::Alphanumeric, rng};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{Chat, InlineKeyboardButton, InlineKeyboardMarkup, Message, ParseMode, User},
+};
+
+use super::{
+    QuestionData, QuestionKey, auth_database,
+    channel_handler::in_required_channels,
+    get_data_by_msg,
+    handler::{Handler, res},
+    penalty,
+    policy::JoinPolicy,
+    update_wating_user, user_finish,
+};
+use crate::{Bot, CONFIG, heuristics, question::QuestionKind, utils::*};
+
+fn policy() -> &'static JoinPolicy {
+    &CONFIG.get().unwrap().verification.join
+}
+
+/// Runs the join verification on an admin without muting, banning or authing, reporting what would have happened.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TestHandler;
+
+impl TestHandler {
+    /// Like [`Handler::send_question`], but with the question kind chosen by the admin instead of the ratios.
+    pub async fn send_test_question(&self, bot: Bot, user: User, chat: Chat, kind: Option<QuestionKind>) -> Result<()> {
+        let mut report = vec![format!("测试验证 {}：", metion_user(&user))];
+        if auth_database::is_authed(user.id, CONFIG.get().unwrap().manage_chat).await? {
+            report.push("已在验证列表中，实际加入时将直接欢迎".to_string());
+        }
+        let grace_minutes = CONFIG.get().unwrap().verification.channels.grace_minutes;
+        match in_required_channels(&bot, user.id).await {
+            Ok(true) => {}
+            Ok(false) if grace_minutes > 0 => report.push(format!(
+                "未加入指定频道，实际加入时将被禁言 {} 分钟等待加入",
+                grace_minutes
+            )),
+            Ok(false) => report.push(format!(
+                "未加入指定频道，实际加入时将被踢出 {} 分钟",
+                policy().master_channel_kick_minutes
+            )),
+            Err(err) => report.push(format!("检查频道失败：{}", err)),
+        }
+        if user.is_premium && policy().premium_skip {
+            report.push("Premium 用户，实际加入时将跳过验证".to_string());
+        }
+        let assessment = heuristics::assess(&bot, &user).await;
+        report.push(format!("首次答错时有 {:.0}% 的概率仍被放行", assessment.score * 100.0));
+        if assessment.is_spam() {
+            report.push("名字将被显示为 &lt;filtered&gt;".to_string());
+        }
+        report.push(format!(
+            "回答时间 {} 分钟，可答错 {} 次，失败将被{}",
+            policy().timeout_minutes,
+            policy().max_wrong_attempts,
+            penalty::describe_ban(penalty::next_ban_minutes(user.id).await?)
+        ));
+        bot.send_message(chat.id, report.join("\n"))
+            .parse_mode(ParseMode::Html)
+            .await?;
+
+        let kind = kind.unwrap_or_else(|| {
+            crate::question::random_kind(policy().text_question_ratio, policy().image_question_ratio)
+        });
+        let deadline = Utc::now() + Duration::minutes(policy().timeout_minutes);
+        let mut data = QuestionData {
+            token: policy()
+                .private_quiz
+                .then(|| rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect()),
+            ..QuestionData::new(user.clone(), chat.id, <Self as Handler>::NAME, kind, deadline)
+        };
+        data.renew_question();
+
+        let msg: Message = super::send_question_message(&bot, &data, None).await?;
+        super::add_wating_user((msg.chat.id, msg.id), data).await?;
+
+        Ok(())
+    }
+}
+
+impl Handler for TestHandler {
+    const NAME: &'static str = "Test";
+
+    type Id = ();
+    async fn send_question(&mut self, bot: Bot, user: User, chat: Chat, _: ()) -> Result<()> {
+        self.send_test_question(bot, user, chat, None).await
+    }
+
+    fn keyboard_patch(&self, keyboard: InlineKeyboardMarkup) -> InlineKeyboardMarkup {
+        keyboard.append_row(vec![
+            InlineKeyboardButton::callback("手动踢出🚫", "admin-ban"),
+            InlineKeyboardButton::callback("手动通过✅", "admin-allow"),
+        ])
+    }
+
+    async fn handle_correct(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        if let Some(data) = user_finish(key).await {
+            finish(bot, data, "回答正确，实际验证中将解除禁言并加入验证列表").await?;
+        }
+        res!("回答正确，测试结束")
+    }
+
+    async fn handle_wrong(&mut self, bot: Bot, key: QuestionKey) -> Result<Option<String>> {
+        let (tried_times, user_id) = {
+            if let Some(data) = get_data_by_msg(&key).await {
+                (data.tried_times, data.user.id)
+            } else {
+                return res!();
+            }
+        };
+        if tried_times >= policy().max_wrong_attempts {
+            let ban_minutes = penalty::next_ban_minutes(user_id).await?;
+            let text = format!("失败次数过多，实际验证中将被{}", penalty::describe_ban(ban_minutes));
+            if let Some(data) = user_finish(key).await {
+                finish(bot, data, &text).await?;
+            }
+            res!(("验证失败，{}", text))
+        } else {
+            if let Some(mut data) = get_data_by_msg(&key).await {
+                data.tried_times += 1;
+                update_wating_user(key, &data).await;
+            }
+            res!((
+                "验证失败，还可以再答错 {} 次",
+                policy().max_wrong_attempts - tried_times - 1
+            ))
+        }
+    }
+
+    async fn handle_other(&mut self, bot: Bot, word: &str, key: QuestionKey) -> Result<Option<String>> {
+        let text = match word {
+            "admin-ban" => "管理员手动踢出，实际验证中将被永久封禁",
+            "admin-allow" => "管理员手动通过，实际验证中将解除禁言",
+            _ => return res!(("未知命令：{}", word)),
+        };
+        if let Some(data) = user_finish(key).await {
+            finish(bot, data, text).await?;
+        }
+        res!()
+    }
+
+    async fn handle_timeout(&mut self, bot: Bot, data: (QuestionKey, QuestionData)) -> Result<()> {
+        let ban_minutes = penalty::next_ban_minutes(data.1.user.id).await?;
+        let text = format!("回答超时，实际验证中将被{}", penalty::describe_ban(ban_minutes));
+        finish(bot, data, &text).await
+    }
+}
+
+async fn finish(bot: Bot, (key, data): (QuestionKey, QuestionData), result: &str) -> Result<()> {
+    super::TO_DELETE_MESSAGE.push(key);
+    if let Some(prompt) = data.prompt {
+        super::TO_DELETE_MESSAGE.push((data.chat_id, prompt));
+    }
+    bot.send_message(
+        data.chat_id,
+        format!("测试验证 {} 结束：{}", metion_user(&data.user), result),
+    )
+    .parse_mode(ParseMode::Html)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Profile heuristics scoring how trustworthy a user looks, from names, bio and account signals.
+
+use fancy_regex::Regex;
+use serde::Deserialize;
+use teloxide::{prelude::*, types::User};
+
+use crate::{Bot, CONFIG};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Pattern {
+    #[serde(deserialize_with = "crate::de_regex")]
+    pub pattern: Regex,
+    /// added to the score when the pattern matches, negative for spam signals
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HeuristicsConfig {
+    /// score of a user without any signal
+    pub base: f64,
+    /// names scored at or below this are shown as `<filtered>`
+    pub spam_score: f64,
+    pub name_patterns: Vec<Pattern>,
+    pub username_patterns: Vec<Pattern>,
+    /// bios are fetched with `get_chat`, only when there are patterns for them
+    pub bio_patterns: Vec<Pattern>,
+    pub premium: f64,
+    pub has_username: f64,
+    /// also needs `get_chat`, skipped when 0
+    pub no_photo: f64,
+    /// ids above this are new accounts
+    pub high_id_above: u64,
+    pub high_id: f64,
+    /// right-to-left overrides or zero-width characters in the name
+    pub suspicious_chars: f64,
+}
+
+impl Default for HeuristicsConfig {
+    fn default() -> Self {
+        Self {
+            base: 0.5,
+            spam_score: 0.0,
+            name_patterns: vec![Pattern {
+                pattern: Regex::new("免费|VPN|梯子").unwrap(),
+                weight: -1.0,
+            }],
+            username_patterns: vec![],
+            bio_patterns: vec![],
+            premium: 0.5,
+            has_username: 0.3,
+            no_photo: 0.0,
+            high_id_above: 8_000_000_000,
+            high_id: 0.0,
+            suspicious_chars: 0.0,
+        }
+    }
+}
+
+/// Score of a user, clamped into `0.0..=1.0`. It is the chance of letting a first wrong answer pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Assessment {
+    pub score: f64,
+}
+
+impl Assessment {
+    pub fn is_spam(&self) -> bool {
+        self.score <= CONFIG.get().unwrap().heuristics.spam_score
+    }
+}
+
+fn matches(patterns: &[Pattern], text: &str) -> f64 {
+    patterns
+        .iter()
+        .filter(|p| p.pattern.is_match(text).unwrap_or(false))
+        .map(|p| p.weight)
+        .sum()
+}
+
+fn has_suspicious_chars(text: &str) -> bool {
+    text.chars().any(|c| {
+        matches!(
+            c,
+            '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}'
+        )
+    })
+}
+
+pub async fn assess(bot: &Bot, user: &User) -> Assessment {
+    let config = &CONFIG.get().unwrap().heuristics;
+    let name = user.full_name();
+
+    let mut score = config.base + matches(&config.name_patterns, &name);
+    if let Some(username) = user.username.as_ref() {
+        score += config.has_username + matches(&config.username_patterns, username);
+    }
+    if user.is_premium {
+        score += config.premium;
+    }
+    if user.id.0 > config.high_id_above {
+        score += config.high_id;
+    }
+    if has_suspicious_chars(&name) {
+        score += config.suspicious_chars;
+    }
+
+    if !config.bio_patterns.is_empty() || config.no_photo != 0.0 {
+        match bot.get_chat(user.id).await {
+            Ok(chat) => {
+                if let Some(bio) = chat.bio() {
+                    score += matches(&config.bio_patterns, bio);
+                }
+                if chat.photo.is_none() {
+                    score += config.no_photo;
+                }
+            }
+            Err(err) => log::warn!("Failed to get profile of {}: {}", user.id, err),
+        }
+    }
+
+    Assessment {
+        score: score.clamp(0.0, 1.0),
+    }
+}
@@ -4,9 +4,14 @@
 
 use std::fmt;
 
-use admin::handler::Handler;
+use admin::{
+    auth_database::{AuthInfo, AuthMethod, Scope},
+    handler::Handler,
+    link_detector::Link,
+};
 use anyhow::Result;
 use fancy_regex::Regex;
+use question::QuestionKind;
 use redis::{AsyncCommands, aio::MultiplexedConnection};
 use serde::{
     Deserialize, Deserializer,
@@ -16,7 +21,7 @@ use teloxide::{
     RequestError,
     dispatching::UpdateFilterExt,
     prelude::*,
-    types::MessageId,
+    types::{ChatJoinRequest, Me, MessageId},
     update_listeners::{self},
     utils::command::BotCommands,
 };
@@ -25,6 +30,7 @@ use utils::EasySendMessage;
 
 pub mod admin;
 pub mod error;
+pub mod heuristics;
 pub mod question;
 pub mod utils;
 
@@ -40,9 +46,21 @@ pub struct Config {
     pub master_channel: ChatId,
     pub watch_list: Vec<UserId>,
     pub questions: Vec<question::Question>,
+    #[serde(default)]
+    pub text_answer: question::TextAnswerConfig,
+    #[serde(default)]
+    pub generator: question::generator::GeneratorConfig,
     #[serde(deserialize_with = "de_regex")]
     pub forward_pattern: Regex,
     pub redis: String,
+    #[serde(default)]
+    pub verification: admin::policy::VerificationConfig,
+    #[serde(default)]
+    pub reputation: admin::reputation::ReputationConfig,
+    #[serde(default)]
+    pub heuristics: heuristics::HeuristicsConfig,
+    #[serde(default)]
+    pub auth: admin::auth_database::AuthConfig,
 }
 
 fn de_regex<'de, D>(de: D) -> Result<Regex, D::Error>
@@ -80,6 +98,7 @@ async fn get_connection() -> MultiplexedConnection {
 }
 
 pub static CONFIG: OnceCell<Config> = OnceCell::const_new();
+pub static ME: OnceCell<Me> = OnceCell::const_new();
 
 const LAST_SENT_KEY: &str = "_shit_bot_last_send_message";
 const LAST_SHIT_KEY: &str = "_shit_bot_last_shit_message";
@@ -94,12 +113,32 @@ async fn main() -> Result<()> {
     f.read_to_end(&mut buf).await?;
 
     let config = serde_yaml::from_slice::<Config>(&buf)?;
+    anyhow::ensure!(
+        !config.questions.is_empty() || config.generator.ratio > 0.0,
+        "config.yaml has no question"
+    );
     CLIENT.set(redis::Client::open(config.redis.clone()).unwrap()).unwrap();
 
     let bot = teloxide::Bot::new(config.token.clone());
 
     CONFIG.set(config)?;
 
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        return admin::auth_transfer::run_cli(&args).await;
+    }
+
+    ME.set(bot.get_me().await?)?;
+
+    admin::register_handlers();
+    admin::scheduler::start(bot.clone());
+    if let Err(err) = admin::resume_wating_users(bot.clone()).await {
+        log::error!("Failed to resume pending questions: {}", err);
+    }
+    if let Err(err) = admin::lockdown::resume().await {
+        log::error!("Failed to resume lockdown: {}", err);
+    }
+
     let handler = dptree::entry()
         .branch(
             Update::filter_chat_member()
@@ -109,6 +148,9 @@ async fn main() -> Result<()> {
                         && update.new_chat_member.is_present()
                 })
                 .endpoint(|bot: Bot, update: ChatMemberUpdated| async move {
+                    if let Err(err) = admin::lockdown::record_join(bot.clone(), &update).await {
+                        log::error!("Failed to record join: {}", err);
+                    }
                     let res = admin::join_handler::JoinHandler
                         .send_question(
                             bot.clone(),
@@ -126,61 +168,78 @@ async fn main() -> Result<()> {
                     Ok(())
                 }),
         )
+        .branch(
+            Update::filter_chat_member()
+                .filter(|update: ChatMemberUpdated| {
+                    update.chat.id == CONFIG.get().unwrap().manage_chat
+                        && update.old_chat_member.is_present()
+                        && !update.new_chat_member.is_present()
+                })
+                .endpoint(|bot: Bot, update: ChatMemberUpdated| async move {
+                    admin::join_handler::handle_leave(bot, update.chat.id, update.new_chat_member.user).await
+                }),
+        )
+        .branch(
+            Update::filter_chat_join_request()
+                .filter(|request: ChatJoinRequest| {
+                    let config = CONFIG.get().unwrap();
+                    request.chat.id == config.manage_chat && config.verification.join_request.enabled
+                })
+                .endpoint(|bot: Bot, request: ChatJoinRequest| async move {
+                    let res = admin::join_request_handler::JoinRequestHandler
+                        .send_question(bot.clone(), request.from.clone(), request.chat.clone(), ())
+                        .await;
+
+                    if let Err(err) = res {
+                        bot.send_message(CONFIG.get().unwrap().admin_log, format!("{}", err))
+                            .await?;
+                        return Err(err);
+                    }
+                    Ok(())
+                }),
+        )
         .branch(
             Update::filter_message()
                 .branch(dptree::filter(|msg: Message| msg.is_automatic_forward()).endpoint(auto_unpin))
                 .branch(
-                    dptree::filter_async(|msg: Message| async move {
-                        if msg.from.is_none() {
-                            return false;
-                        }
+                    dptree::filter_async(|msg: Message| async move { admin::find_text_question(&msg).await.is_some() })
+                        .endpoint(admin::text_answer),
+                )
+                .branch(
+                    dptree::filter_async(|bot: Bot, msg: Message| async move {
+                        admin::lockdown::should_delete(&bot, &msg).await
+                    })
+                    .endpoint(admin::lockdown::delete_message),
+                )
+                .branch(
+                    dptree::filter_map_async(|msg: Message| async move {
+                        let user_id = msg.from.as_ref()?.id;
 
                         if !(msg.chat.id == CONFIG.get().unwrap().manage_chat) {
-                            return false;
+                            return None;
                         }
 
-                        use teloxide::types::MessageEntityKind::*;
-                        let has_link = [msg.entities(), msg.caption_entities()]
-                            .into_iter()
-                            .flatten()
-                            .flatten()
-                            .any(|e| matches!(e.kind, Url | Mention | TextLink { .. } | TextMention { .. }));
-
-                        if !has_link {
-                            return false;
+                        let links = admin::link_detector::detect(&msg);
+                        if links.is_empty() {
+                            return None;
                         }
 
-                        let mut con = crate::get_connection().await;
-
-                        let res = con.sismember(admin::AUTHED_USERS_KEY, msg.from.unwrap().id.0).await;
-                        match res {
-                            Ok(res) =>
-                            {
-                                #[allow(clippy::needless_bool)]
-                                if res {
-                                    false
-                                } else {
-                                    true
-                                }
-                            }
+                        match admin::auth_database::is_authed(user_id, msg.chat.id).await {
+                            Ok(authed) => (!authed).then_some(links),
                             Err(err) => {
                                 log::error!("Redis error: {}", err);
-                                false
+                                None
                             }
                         }
                     })
-                    .endpoint(|bot: Bot, msg: Message| async move {
-                        log::debug!("Potential spam message");
-                        if let Some(user) = msg.from {
-                            let res = admin::link_handler::LinkHandler
-                                .send_question(bot.clone(), user.to_owned(), msg.chat.clone(), msg.id)
-                                .await;
-
-                            if let Err(err) = res {
-                                bot.send_message(CONFIG.get().unwrap().admin_log, format!("{}", err))
-                                    .await?;
-                                return Err(err);
-                            }
+                    .endpoint(|bot: Bot, msg: Message, links: Vec<Link>| async move {
+                        log::debug!("Potential spam message with {:?}", links);
+                        let res = admin::link_handler::check_links(bot.clone(), msg, links).await;
+
+                        if let Err(err) = res {
+                            bot.send_message(CONFIG.get().unwrap().admin_log, format!("{}", err))
+                                .await?;
+                            return Err(err);
                         }
 
                         Ok(())
@@ -220,14 +279,21 @@ async fn main() -> Result<()> {
             dptree::filter(|msg: Message| msg.chat.id == CONFIG.get().unwrap().listen_chat).endpoint(edit_shit),
         ))
         .branch(
-            Update::filter_callback_query().endpoint(|bot: Bot, callback: CallbackQuery| async move {
-                let result = admin::callback(bot.clone(), callback.clone()).await;
-                if let Err(e) = result {
-                    bot.send_message(callback.message.unwrap().chat().id, format!("Error: {}", e))
-                        .await?;
-                }
-                Ok(())
-            }),
+            Update::filter_callback_query()
+                .branch(
+                    dptree::filter(|callback: CallbackQuery| {
+                        callback.data.as_deref() == Some(admin::lockdown::END_CALLBACK)
+                    })
+                    .endpoint(admin::lockdown::end_callback),
+                )
+                .branch(dptree::endpoint(|bot: Bot, callback: CallbackQuery| async move {
+                    let result = admin::callback(bot.clone(), callback.clone()).await;
+                    if let Err(e) = result {
+                        bot.send_message(callback.message.unwrap().chat().id, format!("Error: {}", e))
+                            .await?;
+                    }
+                    Ok(())
+                })),
         );
 
     let polling = update_listeners::polling_default(bot.clone()).await;
@@ -259,6 +325,77 @@ enum Command {
     Pull,
     #[command(description = "屎球堵嘴")]
     Bullshit,
+    #[command(description = "开始私聊验证", hide)]
+    Start(String),
+    #[command(description = "测试入群验证，可指定题型 choice/text/image")]
+    TestVerify(String),
+    #[command(description = "封锁群组，可指定时长如 30m、2h、1h30m")]
+    Lockdown(String),
+    #[command(description = "解除群组封锁")]
+    Unlock,
+    #[command(description = "查看用户的验证失败记录，回复消息或指定用户 ID")]
+    History(String),
+    #[command(description = "将用户加入已验证列表，回复消息或指定用户 ID，可指定 global 或群组 ID")]
+    Auth(String),
+    #[command(description = "撤销用户的验证，回复消息或指定用户 ID，可指定 global 或群组 ID")]
+    Deauth(String),
+    #[command(description = "查看用户的验证信息，回复消息或指定用户 ID")]
+    Authinfo(String),
+    #[command(description = "导入已验证用户，回复 JSON、CSV 或 Telegram 导出的 result.json，可指定 global 或群组 ID")]
+    ImportAuth(String),
+    #[command(description = "导出已验证用户，可指定格式 json/csv 以及 global 或群组 ID")]
+    ExportAuth(String),
+    #[command(description = "查看或修改链接黑白名单，如 allow github.com、deny @spam、remove github.com")]
+    Linklist(String),
+}
+
+impl Command {
+    /// Commands only admins may use, see [`is_privileged`].
+    fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Command::Bullshit
+                | Command::TestVerify(_)
+                | Command::Lockdown(_)
+                | Command::Unlock
+                | Command::History(_)
+                | Command::Auth(_)
+                | Command::Deauth(_)
+                | Command::Authinfo(_)
+                | Command::ImportAuth(_)
+                | Command::ExportAuth(_)
+                | Command::Linklist(_)
+        )
+    }
+}
+
+/// Admins of `to_chat` may use the admin commands and the lockdown button.
+async fn is_privileged(bot: &Bot, user_id: UserId) -> bool {
+    bot.get_chat_member(CONFIG.get().unwrap().to_chat, user_id)
+        .await
+        .map(|c| c.is_privileged())
+        .unwrap_or(false)
+}
+
+/// User given by id in the command argument, or the sender of the replied message.
+fn target_user(message: &Message, arg: &str) -> Option<UserId> {
+    match arg.trim() {
+        "" => message.reply_to_message()?.from.as_ref().map(|user| user.id),
+        arg => arg.parse().ok().map(UserId),
+    }
+}
+
+/// Splits the auth scope, `global` or a chat id, from the rest of the command argument.
+fn split_scope(arg: &str) -> (Option<Scope>, String) {
+    let mut scope = None;
+    let mut rest = Vec::new();
+    for word in arg.split_whitespace() {
+        match Scope::parse(word) {
+            Some(parsed) if word == "global" || word.starts_with('-') => scope = Some(parsed),
+            _ => rest.push(word),
+        }
+    }
+    (scope, rest.join(" "))
 }
 
 async fn command_handle(bot: Bot, message: Message, command: Command) -> Result<()> {
@@ -266,11 +403,25 @@ async fn command_handle(bot: Bot, message: Message, command: Command) -> Result<
         return Ok(());
     }
     let config = CONFIG.get().unwrap();
+    if command.is_admin() && !is_privileged(&bot, message.from.as_ref().unwrap().id).await {
+        bot.send_message(message.chat.id, "你没有权限使用此命令")
+            .reply_to_message_id(message.id)
+            .await?;
+        return Ok(());
+    }
     match command {
         Command::Help => {
             bot.send_message(message.chat.id, Command::descriptions().to_string())
                 .await?;
         }
+        Command::Start(arg) => {
+            if let Some(token) = arg.strip_prefix("verify_") {
+                admin::join_handler::start_private_verify(bot, message, token).await?;
+                return Ok(());
+            }
+            bot.send_message(message.chat.id, Command::descriptions().to_string())
+                .await?;
+        }
         Command::Source => {
             bot.send_message(message.chat.id, "https://github.com/NT3Games/shit_bot")
                 .await?;
@@ -320,18 +471,134 @@ async fn command_handle(bot: Bot, message: Message, command: Command) -> Result<
                 .reply_to_message_id(message.id)
                 .await?;
         }
-        Command::Bullshit => {
-            let privileged = bot
-                .get_chat_member(config.to_chat, message.from.as_ref().unwrap().id)
-                .await
-                .map(|c| c.is_privileged())
-                .unwrap_or(false);
-            if !privileged {
-                bot.send_message(message.chat.id, "你没有权限使用此命令")
+        Command::TestVerify(kind) => {
+            let kind = match kind.trim() {
+                "" => None,
+                "choice" => Some(QuestionKind::Choice),
+                "text" => Some(QuestionKind::Text),
+                "image" => Some(QuestionKind::Image),
+                other => {
+                    bot.send_message(message.chat.id, format!("未知题型：{}", other))
+                        .reply_to_message_id(message.id)
+                        .await?;
+                    return Ok(());
+                }
+            };
+            admin::test_handler::TestHandler
+                .send_test_question(bot, message.from.clone().unwrap(), message.chat.clone(), kind)
+                .await?;
+        }
+        Command::Lockdown(duration) => {
+            let until = if duration.trim().is_empty() {
+                None
+            } else if let Some(duration) = admin::lockdown::parse_duration(&duration) {
+                Some(chrono::Utc::now() + duration)
+            } else {
+                bot.send_message(message.chat.id, format!("无法识别的时长：{}", duration))
                     .reply_to_message_id(message.id)
                     .await?;
                 return Ok(());
-            }
+            };
+            let reason = format!("管理员 {} 手动封锁", utils::metion_user(message.from.as_ref().unwrap()));
+            let text = if admin::lockdown::start(bot.clone(), config.manage_chat, reason, until).await? {
+                "群组已封锁"
+            } else {
+                "群组已处于封锁状态"
+            };
+            bot.send_message(message.chat.id, text)
+                .reply_to_message_id(message.id)
+                .await?;
+        }
+        Command::Unlock => {
+            let text = if admin::lockdown::end(bot.clone()).await? {
+                "已解除封锁"
+            } else {
+                "群组未处于封锁状态"
+            };
+            bot.send_message(message.chat.id, text)
+                .reply_to_message_id(message.id)
+                .await?;
+        }
+        Command::History(user_id) => {
+            let user_id = if let Some(user_id) = target_user(&message, &user_id) {
+                user_id
+            } else {
+                bot.send_message(message.chat.id, "请回复一条消息或指定用户 ID")
+                    .reply_to_message_id(message.id)
+                    .await?;
+                return Ok(());
+            };
+            let failures = admin::penalty::history(user_id).await?;
+            bot.send_message(message.chat.id, admin::penalty::format_history(user_id, &failures))
+                .reply_to_message_id(message.id)
+                .parse_mode(teloxide::types::ParseMode::Html)
+                .await?;
+        }
+        Command::Auth(ref user_id) | Command::Deauth(ref user_id) | Command::Authinfo(ref user_id) => {
+            let admin = message.from.as_ref().unwrap().id;
+            let (scope, user_id) = split_scope(user_id);
+            let user_id = if let Some(user_id) = target_user(&message, &user_id) {
+                user_id
+            } else {
+                bot.send_message(message.chat.id, "请回复一条消息或指定用户 ID")
+                    .reply_to_message_id(message.id)
+                    .await?;
+                return Ok(());
+            };
+            let text = match command {
+                Command::Auth(_) => {
+                    let scope = scope.unwrap_or_else(Scope::main);
+                    let info = AuthInfo::new(AuthMethod::AdminAllow, Some(message.chat.id)).by(admin);
+                    admin::auth_database::add_authed(user_id, scope, info).await?;
+                    format!("已将 {} 加入已验证列表（{}）", user_id, scope)
+                }
+                Command::Deauth(_) => {
+                    // revoked everywhere unless a scope is given
+                    let scopes = match scope {
+                        Some(scope) => vec![scope],
+                        None => admin::auth_database::scopes().await?,
+                    };
+                    let mut revoked = Vec::new();
+                    for scope in scopes {
+                        if admin::auth_database::remove_authed(user_id, scope).await? {
+                            revoked.push(scope.to_string());
+                        }
+                    }
+                    if revoked.is_empty() {
+                        format!("{} 未通过验证", user_id)
+                    } else {
+                        format!("已撤销 {} 的验证（{}）", user_id, revoked.join("、"))
+                    }
+                }
+                _ => {
+                    let infos = admin::auth_database::auth_info(user_id).await?;
+                    if infos.is_empty() {
+                        format!("{} 未通过验证", user_id)
+                    } else {
+                        let infos = infos
+                            .iter()
+                            .map(|(scope, info)| format!("[{}]\n{}", scope, info))
+                            .collect::<Vec<_>>()
+                            .join("\n\n");
+                        format!("{} 已通过验证\n{}", user_id, infos)
+                    }
+                }
+            };
+            bot.send_message(message.chat.id, text)
+                .reply_to_message_id(message.id)
+                .await?;
+        }
+        Command::ImportAuth(scope) => {
+            admin::auth_transfer::import_command(bot, &message, split_scope(&scope).0).await?;
+        }
+        Command::ExportAuth(format) => {
+            let (scope, format) = split_scope(&format);
+            admin::auth_transfer::export_command(bot, &message, &format, scope).await?;
+        }
+        Command::Linklist(arg) => {
+            admin::link_lists::command(bot, &message, &arg).await?;
+        }
+        Command::Bullshit => {
             if let Some(reply) = message.reply_to_message() {
                 let (res, name) = if let Some(sender) = reply.sender_chat.as_ref() {
                     (
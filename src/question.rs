@@ -1,30 +1,144 @@
 use rand::{Rng, rng, seq::IndexedRandom};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::CONFIG;
 
+pub mod captcha;
+pub mod generator;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Question {
     pub title: String,
     pub contrary: Option<String>,
+    #[serde(default)]
     pub wrong: Vec<String>,
     pub correct: Vec<String>,
+    #[serde(default)]
+    pub kind: QuestionKind,
 }
 
-pub fn new_question() -> (&'static String, Vec<&'static String>, usize) {
-    let mut rng = rng();
-    let question = CONFIG.get().unwrap().questions.choose(&mut rng).expect("no question");
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuestionKind {
+    /// pick the correct option from the buttons
+    #[default]
+    Choice,
+    /// type one of the correct answers
+    Text,
+    /// pick the characters shown in a rendered captcha image, never read from the bank
+    Image,
+}
 
-    let (title, correct_answers, wrong_answers) = if question.contrary.is_some() && rng.random_bool(0.5) {
-        (question.contrary.as_ref().unwrap(), &question.wrong, &question.correct)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TextAnswerConfig {
+    pub ignore_case: bool,
+    pub ignore_whitespace: bool,
+    pub ignore_punctuation: bool,
+    /// treat full-width characters as their ASCII counterparts
+    pub half_width: bool,
+}
+
+impl Default for TextAnswerConfig {
+    fn default() -> Self {
+        Self {
+            ignore_case: true,
+            ignore_whitespace: true,
+            ignore_punctuation: false,
+            half_width: true,
+        }
+    }
+}
+
+/// Picks the kind of the next question, only among the kinds the bank has questions of.
+pub fn random_kind(text_ratio: f64, image_ratio: f64) -> QuestionKind {
+    let mut rng = rng();
+    let has_choice = available(QuestionKind::Choice);
+    let has_text = available(QuestionKind::Text);
+    if rng.random_bool(image_ratio.clamp(0.0, 1.0)) {
+        QuestionKind::Image
+    } else if has_text && (!has_choice || rng.random_bool(text_ratio.clamp(0.0, 1.0))) {
+        QuestionKind::Text
     } else {
-        (&question.title, &question.correct, &question.wrong)
+        QuestionKind::Choice
+    }
+}
+
+/// Whether a question of `kind` can be asked, image and generated questions need no bank.
+pub fn available(kind: QuestionKind) -> bool {
+    match kind {
+        QuestionKind::Image => true,
+        QuestionKind::Choice if CONFIG.get().unwrap().generator.ratio > 0.0 => true,
+        kind => !questions_of(kind).is_empty(),
+    }
+}
+
+fn questions_of(kind: QuestionKind) -> Vec<&'static Question> {
+    CONFIG
+        .get()
+        .unwrap()
+        .questions
+        .iter()
+        .filter(|q| q.kind == kind)
+        .collect()
+}
+
+/// A choice question from the bank, or a generated one with the probability `generator.ratio`.
+pub fn new_question() -> (String, Vec<String>, usize) {
+    let mut rng = rng();
+    let ratio = CONFIG.get().unwrap().generator.ratio.clamp(0.0, 1.0);
+    let question = match questions_of(QuestionKind::Choice).choose(&mut rng) {
+        Some(question) if !rng.random_bool(ratio) => *question,
+        _ => return generator::generate(),
     };
 
+    let (title, correct_answers, wrong_answers) =
+        if let Some(contrary) = question.contrary.as_ref().filter(|_| rng.random_bool(0.5)) {
+            (contrary, &question.wrong, &question.correct)
+        } else {
+            (&question.title, &question.correct, &question.wrong)
+        };
+
     let correct = correct_answers.choose(&mut rng).expect("no correct answer");
     let mut options = wrong_answers.choose_multiple(&mut rng, 3).collect::<Vec<_>>();
     let correct_idx = rng.random_range(0..=options.len());
     options.insert(correct_idx, correct);
 
-    (title, options, correct_idx)
+    (title.clone(), options.into_iter().cloned().collect(), correct_idx)
+}
+
+pub fn new_text_question() -> (String, Vec<String>) {
+    let question = *questions_of(QuestionKind::Text)
+        .choose(&mut rng())
+        .expect("no text question");
+    (question.title.clone(), question.correct.clone())
+}
+
+pub fn normalize_answer(answer: &str) -> String {
+    let config = &CONFIG.get().unwrap().text_answer;
+    let answer: String = answer
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '\u{3000}' if config.half_width => ' ',
+            '\u{ff01}'..='\u{ff5e}' if config.half_width => char::from_u32(c as u32 - 0xfee0).unwrap_or(c),
+            _ => c,
+        })
+        .filter(|c| !(config.ignore_whitespace && c.is_whitespace()))
+        .filter(|c| !(config.ignore_punctuation && (c.is_ascii_punctuation() || is_cjk_punctuation(*c))))
+        .collect();
+    if config.ignore_case {
+        answer.to_lowercase()
+    } else {
+        answer
+    }
+}
+
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303f}' | '\u{ff00}'..='\u{ff0f}' | '\u{ff1a}'..='\u{ff20}')
+}
+
+pub fn check_answer(answer: &str, correct: &[String]) -> bool {
+    let answer = normalize_answer(answer);
+    !answer.is_empty() && correct.iter().any(|c| normalize_answer(c) == answer)
 }
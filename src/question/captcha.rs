@@ -0,0 +1,199 @@
+//! Distorted text captcha rendered to PNG in process, no external service involved.
+
+use std::f64::consts::PI;
+
+use anyhow::Result;
+use rand::{Rng, rng, seq::IndexedRandom};
+
+const WIDTH: usize = 240;
+const HEIGHT: usize = 80;
+const SCALE: usize = 7;
+const CODE_LEN: usize = 5;
+
+// characters that are hard to confuse with each other, e.g. no 0/O or 1/I
+const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPRSTUVWXYZ23456789";
+
+// 5x7 bitmap glyphs, one row per byte with the leftmost pixel in the highest bit
+fn glyph(c: u8) -> [u8; 7] {
+    match c {
+        b'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        b'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        b'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        b'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        b'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        b'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        b'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        b'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        b'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        b'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        b'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        b'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        b'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        b'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        b'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        b'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        b'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        b'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        b'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        b'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        b'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        b'Y' => [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100],
+        b'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        b'2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        b'3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        b'4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        b'5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        b'6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        b'7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        b'8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        b'9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        _ => [0; 7],
+    }
+}
+
+fn random_code() -> String {
+    let mut rng = rng();
+    (0..CODE_LEN)
+        .map(|_| *ALPHABET.choose(&mut rng).unwrap() as char)
+        .collect()
+}
+
+/// Builds the captcha question, the correct option is the code rendered by [`render`].
+pub fn new_image_question() -> (String, Vec<String>, usize) {
+    let mut rng = rng();
+    let code = random_code();
+
+    // decoys are independent codes, ones derived from the code would let the majority of the buttons give it away
+    let mut options = Vec::new();
+    while options.len() < 3 {
+        let decoy = random_code();
+        if decoy != code && !options.contains(&decoy) {
+            options.push(decoy);
+        }
+    }
+    let correct_idx = rng.random_range(0..=options.len());
+    options.insert(correct_idx, code);
+
+    ("请选择图片中的字符".to_string(), options, correct_idx)
+}
+
+struct Canvas {
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    fn new(background: [u8; 3]) -> Self {
+        Self {
+            pixels: vec![background; WIDTH * HEIGHT],
+        }
+    }
+
+    fn set(&mut self, x: f64, y: f64, color: [u8; 3]) {
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        if x < WIDTH && y < HEIGHT {
+            self.pixels[y * WIDTH + x] = color;
+        }
+    }
+
+    fn get(&self, x: f64, y: f64) -> Option<[u8; 3]> {
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let (x, y) = (x as usize, y as usize);
+        (x < WIDTH && y < HEIGHT).then(|| self.pixels[y * WIDTH + x])
+    }
+}
+
+fn random_color(rng: &mut impl Rng, range: std::ops::Range<u8>) -> [u8; 3] {
+    [
+        rng.random_range(range.clone()),
+        rng.random_range(range.clone()),
+        rng.random_range(range),
+    ]
+}
+
+/// Renders `code` with sheared glyphs, a sine warp and noise, returns the PNG bytes.
+pub fn render(code: &str) -> Result<Vec<u8>> {
+    let mut rng = rng();
+    let background = random_color(&mut rng, 200..255);
+    let mut canvas = Canvas::new(background);
+
+    let cell = WIDTH / (code.len() + 1);
+    let glyph_height = (7 * SCALE) as f64;
+    for (i, c) in code.bytes().enumerate() {
+        let color = random_color(&mut rng, 0..120);
+        let shear = rng.random_range(-0.35..0.35);
+        let x0 = (cell / 2 + i * cell) as f64 + rng.random_range(-4.0..4.0);
+        let y0 = (HEIGHT as f64 - glyph_height) / 2.0 + rng.random_range(-6.0..6.0);
+
+        for (gy, row) in glyph(c).iter().enumerate() {
+            for gx in 0..5 {
+                if row & (0b10000 >> gx) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let y = (gy * SCALE + dy) as f64;
+                        let x = (gx * SCALE + dx) as f64 + shear * (y - glyph_height / 2.0);
+                        canvas.set(x0 + x, y0 + y, color);
+                    }
+                }
+            }
+        }
+    }
+
+    // sine warp, sampled backwards so that the result has no holes
+    let (amp_x, amp_y) = (rng.random_range(2.0..5.0), rng.random_range(2.0..5.0));
+    let (period_x, period_y) = (rng.random_range(30.0..60.0), rng.random_range(40.0..90.0));
+    let (phase_x, phase_y) = (rng.random_range(0.0..2.0 * PI), rng.random_range(0.0..2.0 * PI));
+    let mut warped = Canvas::new(background);
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let (xf, yf) = (x as f64, y as f64);
+            let sx = xf + amp_x * (2.0 * PI * yf / period_x + phase_x).sin();
+            let sy = yf + amp_y * (2.0 * PI * xf / period_y + phase_y).sin();
+            if let Some(color) = canvas.get(sx, sy) {
+                warped.set(xf, yf, color);
+            }
+        }
+    }
+
+    for _ in 0..5 {
+        let color = random_color(&mut rng, 0..200);
+        let (x1, y1) = (
+            rng.random_range(0.0..WIDTH as f64),
+            rng.random_range(0.0..HEIGHT as f64),
+        );
+        let (x2, y2) = (
+            rng.random_range(0.0..WIDTH as f64),
+            rng.random_range(0.0..HEIGHT as f64),
+        );
+        let steps = ((x2 - x1).abs().max((y2 - y1).abs()) as usize).max(1);
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            warped.set(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t, color);
+            warped.set(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t + 1.0, color);
+        }
+    }
+    for _ in 0..WIDTH * HEIGHT / 20 {
+        let color = random_color(&mut rng, 0..255);
+        warped.set(
+            rng.random_range(0.0..WIDTH as f64),
+            rng.random_range(0.0..HEIGHT as f64),
+            color,
+        );
+    }
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buf, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&warped.pixels.concat())?;
+    }
+    Ok(buf)
+}
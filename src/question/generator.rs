@@ -0,0 +1,245 @@
+//! Questions built at runtime, so that the answers can not be memorized from the static bank.
+
+use rand::{Rng, rng, seq::IndexedRandom};
+use serde::Deserialize;
+
+use crate::CONFIG;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GeneratorConfig {
+    /// probability of a generated question instead of one from the bank
+    pub ratio: f64,
+    /// upper bound of the operands in arithmetic questions, at most 999
+    pub max_operand: u32,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            ratio: 0.0,
+            max_operand: 50,
+        }
+    }
+}
+
+// category name and its members, separated by spaces
+const CATEGORIES: &[(&str, &str)] = &[
+    ("水果", "苹果 香蕉 葡萄 西瓜 橘子 草莓 菠萝 芒果 荔枝 桃子"),
+    ("动物", "老虎 大象 猴子 熊猫 兔子 长颈鹿 狐狸 企鹅 骆驼 松鼠"),
+    ("颜色", "红色 黄色 蓝色 绿色 紫色 白色 黑色 橙色 灰色 粉色"),
+    ("交通工具", "汽车 火车 飞机 轮船 自行车 地铁 摩托车 公交车 电车 直升机"),
+    ("乐器", "钢琴 吉他 小提琴 二胡 笛子 鼓 琵琶 古筝 萨克斯 竖琴"),
+];
+
+const DIGITS: [&str; 10] = ["零", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+/// Writes numbers below 10000 in Chinese, e.g. 105 as 一百零五.
+pub fn chinese_number(n: u32) -> String {
+    if n == 0 {
+        return DIGITS[0].to_string();
+    }
+    if n < 10 {
+        return DIGITS[n as usize].to_string();
+    }
+    if n == 10 {
+        return "十".to_string();
+    }
+    if n < 20 {
+        return format!("十{}", DIGITS[(n % 10) as usize]);
+    }
+
+    let mut result = String::new();
+    let mut pending_zero = false;
+    for (unit, value) in [("千", 1000), ("百", 100), ("十", 10), ("", 1)] {
+        let digit = n / value % 10;
+        if digit == 0 {
+            pending_zero = !result.is_empty();
+            continue;
+        }
+        if pending_zero {
+            result.push_str(DIGITS[0]);
+            pending_zero = false;
+        }
+        result.push_str(DIGITS[digit as usize]);
+        result.push_str(unit);
+    }
+    result
+}
+
+type Generated = (String, Vec<String>, usize);
+
+pub fn generate() -> Generated {
+    let max_operand = CONFIG.get().unwrap().generator.max_operand;
+    match rng().random_range(0..3) {
+        0 => arithmetic(max_operand),
+        1 => odd_one_out(),
+        _ => largest_number(max_operand),
+    }
+}
+
+fn shuffle_in(correct: String, wrong: Vec<String>) -> (Vec<String>, usize) {
+    let mut options = wrong;
+    let correct_idx = rng().random_range(0..=options.len());
+    options.insert(correct_idx, correct);
+    (options, correct_idx)
+}
+
+fn arithmetic(max_operand: u32) -> Generated {
+    let mut rng = rng();
+    let max = max_operand.clamp(2, 999);
+    let a = rng.random_range(1..=max);
+    let b = rng.random_range(1..=max);
+
+    let (title, answer) = match rng.random_range(0..3) {
+        0 => (
+            format!("{}加{}等于几？", chinese_number(a), chinese_number(b)),
+            (a + b) as i64,
+        ),
+        1 => {
+            let (a, b) = (a.max(b), a.min(b));
+            (
+                format!("{}减{}等于几？", chinese_number(a), chinese_number(b)),
+                (a - b) as i64,
+            )
+        }
+        _ => {
+            let b = b % 10 + 1;
+            (
+                format!("{}乘以{}等于几？", chinese_number(a), chinese_number(b)),
+                (a * b) as i64,
+            )
+        }
+    };
+
+    let mut wrong = Vec::new();
+    while wrong.len() < 3 {
+        let offset = rng.random_range(1..=10) * if rng.random_bool(0.5) { 1 } else { -1 };
+        let candidate = (answer + offset).to_string();
+        if answer + offset >= 0 && !wrong.contains(&candidate) {
+            wrong.push(candidate);
+        }
+    }
+
+    let (options, correct_idx) = shuffle_in(answer.to_string(), wrong);
+    (title, options, correct_idx)
+}
+
+fn odd_one_out() -> Generated {
+    let mut rng = rng();
+    let picked = CATEGORIES.choose_multiple(&mut rng, 2).collect::<Vec<_>>();
+    let (name, members) = picked[0];
+    let members = members.split(' ').collect::<Vec<_>>();
+    let others = picked[1].1.split(' ').collect::<Vec<_>>();
+
+    let wrong = members.choose_multiple(&mut rng, 3).map(|s| s.to_string()).collect();
+    let correct = others.choose(&mut rng).unwrap().to_string();
+
+    let (options, correct_idx) = shuffle_in(correct, wrong);
+    (format!("以下哪个不是{}？", name), options, correct_idx)
+}
+
+fn largest_number(max_operand: u32) -> Generated {
+    let mut rng = rng();
+    let max = max_operand.clamp(4, 999) * 10;
+    let mut numbers = Vec::new();
+    while numbers.len() < 4 {
+        let n = rng.random_range(1..=max);
+        if !numbers.contains(&n) {
+            numbers.push(n);
+        }
+    }
+
+    let correct_idx = numbers.iter().enumerate().max_by_key(|(_, n)| **n).unwrap().0;
+    let options = numbers.into_iter().map(chinese_number).collect();
+    ("以下哪个数最大？".to_string(), options, correct_idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_chinese(text: &str) -> u32 {
+        let (mut total, mut digit) = (0, 0);
+        for c in text.chars() {
+            match c {
+                '零' => {}
+                '十' => (total, digit) = (total + digit.max(1) * 10, 0),
+                '百' => (total, digit) = (total + digit * 100, 0),
+                '千' => (total, digit) = (total + digit * 1000, 0),
+                c => digit = DIGITS.iter().position(|d| d.starts_with(c)).unwrap() as u32,
+            }
+        }
+        total + digit
+    }
+
+    #[test]
+    fn chinese_number_spelling() {
+        for (n, text) in [
+            (0, "零"),
+            (1, "一"),
+            (5, "五"),
+            (9, "九"),
+            (10, "十"),
+            (15, "十五"),
+            (20, "二十"),
+            (105, "一百零五"),
+            (110, "一百一十"),
+            (1001, "一千零一"),
+            (9999, "九千九百九十九"),
+        ] {
+            assert_eq!(chinese_number(n), text);
+        }
+    }
+
+    #[test]
+    fn chinese_number_round_trip() {
+        for n in 0..10000 {
+            assert_eq!(parse_chinese(&chinese_number(n)), n, "{}", chinese_number(n));
+        }
+    }
+
+    #[test]
+    fn arithmetic_answer_matches_title() {
+        for _ in 0..500 {
+            let (title, options, correct) = arithmetic(50);
+            let expr = title.strip_suffix("等于几？").unwrap();
+            let answer = if let Some((a, b)) = expr.split_once('加') {
+                parse_chinese(a) as i64 + parse_chinese(b) as i64
+            } else if let Some((a, b)) = expr.split_once("乘以") {
+                parse_chinese(a) as i64 * parse_chinese(b) as i64
+            } else {
+                let (a, b) = expr.split_once('减').unwrap();
+                parse_chinese(a) as i64 - parse_chinese(b) as i64
+            };
+            assert_eq!(options[correct], answer.to_string(), "{}", title);
+            assert_eq!(options.iter().filter(|option| **option == options[correct]).count(), 1);
+        }
+    }
+
+    #[test]
+    fn odd_one_out_answer_is_outside_category() {
+        for _ in 0..100 {
+            let (title, options, correct) = odd_one_out();
+            let name = title.strip_prefix("以下哪个不是").unwrap().strip_suffix("？").unwrap();
+            let members = CATEGORIES.iter().find(|(category, _)| *category == name).unwrap().1;
+            for (idx, option) in options.iter().enumerate() {
+                assert_eq!(
+                    members.split(' ').any(|member| member == option),
+                    idx != correct,
+                    "{}",
+                    title
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn largest_number_answer_is_largest() {
+        for _ in 0..100 {
+            let (_, options, correct) = largest_number(50);
+            let numbers = options.iter().map(|option| parse_chinese(option)).collect::<Vec<_>>();
+            assert_eq!(numbers[correct], *numbers.iter().max().unwrap(), "{:?}", options);
+        }
+    }
+}
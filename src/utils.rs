@@ -22,24 +22,6 @@ pub fn metion_user(user: &User) -> String {
     }
 }
 
-pub fn is_spam_name(name: &str) -> bool {
-    name.contains("免费") || name.contains("VPN") || name.contains("梯子")
-}
-
-pub fn rank_user(user: &User) -> f64 {
-    if user.is_premium {
-        return 1.0;
-    }
-    if is_spam_name(&user.full_name()) {
-        return 0.0;
-    }
-    let mut result = 0.5;
-    if user.username.is_some() {
-        result += 0.3;
-    }
-    result
-}
-
 pub async fn send_and_delete_join_result(bot: Bot, chat_id: ChatId, message: String) -> Result<()> {
     bot.send_message(crate::CONFIG.get().unwrap().admin_log, message.clone())
         .parse_mode(ParseMode::Html)